@@ -1,10 +1,11 @@
 use js_sys::Reflect;
-use stage0::h;
 use stage0::synthetic_events::setup_synthetic_event;
+use stage0::{h, Refs};
 use std::cell::RefCell;
 use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
+use web_sys::{HtmlElement, Text};
 
 const VIEW: &str = "
 <div>
@@ -14,6 +15,13 @@ const VIEW: &str = "
 </div>
 ";
 
+#[derive(Refs)]
+struct ViewRefs {
+    count: Text,
+    down: HtmlElement,
+    up: HtmlElement,
+}
+
 struct State {
     count: i32,
 }
@@ -21,13 +29,13 @@ struct State {
 #[wasm_bindgen]
 pub fn main() -> Result<(), JsValue> {
     let root = h(VIEW)?;
-    let mut refs = root.collect()?;
+    let ViewRefs { count, down, up } = root.collect_into()?;
 
-    setup_synthetic_event("click");
+    setup_synthetic_event("click")?;
 
     let state = Rc::new(RefCell::new(State { count: 0 }));
 
-    let count = Rc::new(refs.remove("count").unwrap());
+    let count = Rc::new(count);
 
     let update = {
         let state = state.clone();
@@ -37,7 +45,6 @@ pub fn main() -> Result<(), JsValue> {
     };
     update();
 
-    let down = refs.remove("down").unwrap();
     let down_onclick = {
         let state = state.clone();
         let update = update.clone();
@@ -54,7 +61,6 @@ pub fn main() -> Result<(), JsValue> {
     .unwrap();
     down_onclick.forget();
 
-    let up = refs.remove("up").unwrap();
     let up_onclick = {
         let state = state.clone();
         let update = update.clone();
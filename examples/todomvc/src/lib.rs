@@ -1,12 +1,11 @@
-use js_sys::{Date, Reflect};
+use js_sys::Date;
 use serde::{Deserialize, Serialize};
-use stage0::h;
-use stage0::reconcile::reconcile;
+use stage0::{h_with, CompileOptions, KeyedList, Refs};
 use std::cell::RefCell;
 use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
-use web_sys::{console, HtmlElement, HtmlInputElement, KeyboardEvent, Node};
+use web_sys::{console, HtmlElement, HtmlInputElement, KeyboardEvent, Node, Text};
 
 const TODO_VIEW: &str = r#"
 <li>
@@ -16,6 +15,13 @@ const TODO_VIEW: &str = r#"
 </li>
 "#;
 
+#[derive(Refs)]
+struct TodoRefs {
+    checkbox: HtmlInputElement,
+    label: Text,
+    destroy: HtmlElement,
+}
+
 #[derive(PartialEq, Serialize, Deserialize, Clone)]
 struct Todo {
     id: u64,
@@ -24,46 +30,59 @@ struct Todo {
 }
 
 struct Scope {
-    render: Box<dyn Fn()>,
-    delete: Box<dyn Fn(&Todo)>,
-    // todo_template: stage0::Template,
+    toggle: Rc<dyn Fn(u64, bool)>,
+    delete: Rc<dyn Fn(&Todo)>,
+    todo_template: stage0::Template,
+}
+
+// The views are indented for readability, so drop the whitespace between their blocks.
+fn view(source: &str) -> Result<stage0::Template, JsValue> {
+    let options = CompileOptions {
+        collapse_whitespace: true,
+        ..CompileOptions::default()
+    };
+    Ok(h_with(source, &options)?)
 }
 
-fn todo_view(item: Rc<RefCell<Todo>>, scope: &Scope) -> Result<stage0::Template, JsValue> {
-    let root = h(TODO_VIEW)?;
+fn todo_view(item: &Todo, scope: &Scope) -> Result<stage0::Instance, JsValue> {
+    let mut root = scope.todo_template.instantiate()?;
     {
         let root_node: &Node = root.as_ref();
         root_node
             .unchecked_ref::<HtmlElement>()
-            .set_class_name(if item.borrow().completed {
-                "completed"
-            } else {
-                ""
-            });
+            .set_class_name(if item.completed { "completed" } else { "" });
     }
-    let mut refs = root.collect()?;
-
-    let scope = Rc::new(scope);
+    let TodoRefs {
+        checkbox,
+        label,
+        destroy,
+    } = root.collect_into()?;
 
-    let label = refs.remove("label").unwrap();
-    label.set_node_value(Some(&item.borrow().title));
+    label.set_node_value(Some(&item.title));
 
-    let checkbox = refs
-        .remove("checkbox")
-        .unwrap()
-        .unchecked_into::<HtmlInputElement>();
-    checkbox.set_checked(item.borrow().completed);
+    checkbox.set_checked(item.completed);
 
     {
-        let item = item.clone();
+        let toggle = scope.toggle.clone();
+        let id = item.id;
+        let input = checkbox.clone();
         let onchange = Closure::wrap(Box::new(move || {
-            item.borrow_mut().completed = checkbox.checked();
-            // (scope.render)();
+            toggle(id, input.checked());
         }) as Box<dyn Fn()>);
         checkbox.set_onchange(Some(onchange.as_ref().unchecked_ref()));
+        onchange.forget();
+    }
+
+    {
+        let delete = scope.delete.clone();
+        let item = item.clone();
+        let onclick = Closure::wrap(Box::new(move || {
+            delete(&item);
+        }) as Box<dyn Fn()>);
+        destroy.set_onclick(Some(onclick.as_ref().unchecked_ref()));
+        onclick.forget();
     }
 
-    let destroy = refs.remove("destroy").unwrap();
     Ok(root)
 }
 
@@ -78,7 +97,7 @@ const MAIN_VIEW: &str = r##"
         <label for="toggle-all">Mark all as complete</label>
         <ul class="todo-list" #list></ul>
         <footer class="footer">
-            <span class="todo-count">#count</span>
+            <span class="todo-count"><strong>#count</strong> item#plural left</span>
             <ul class="filters">
                 <li>
                 <a href="#/" class="selected" #all>All</a>
@@ -96,6 +115,20 @@ const MAIN_VIEW: &str = r##"
 </section>
 "##;
 
+#[derive(Refs)]
+struct MainRefs {
+    input: HtmlInputElement,
+    body: HtmlElement,
+    toggleall: HtmlInputElement,
+    list: Node,
+    count: Text,
+    plural: Text,
+    all: HtmlElement,
+    active: HtmlElement,
+    completed: HtmlElement,
+    clear: HtmlElement,
+}
+
 enum Filter {
     All,
     Active,
@@ -103,48 +136,61 @@ enum Filter {
 }
 
 fn main_view(todos: Vec<Todo>) -> Result<stage0::Template, JsValue> {
-    let root = h(MAIN_VIEW)?;
-    let mut refs = root.collect()?;
+    let root = view(MAIN_VIEW)?;
+    let MainRefs {
+        input,
+        body,
+        toggleall,
+        list,
+        count,
+        plural,
+        all,
+        active,
+        completed,
+        clear,
+    } = root.collect_into()?;
 
     let filter = Rc::new(RefCell::new(Filter::All));
 
-    let body = refs.remove("body").unwrap().unchecked_into::<HtmlElement>();
-
-    let clear = refs
-        .remove("clear")
-        .unwrap()
-        .unchecked_into::<HtmlElement>();
-
-    let input = Rc::new(
-        refs.remove("input")
-            .unwrap()
-            .unchecked_into::<HtmlInputElement>(),
-    );
+    let input = Rc::new(input);
 
     let todos = Rc::new(RefCell::new(todos));
-    let rendered_ids: Rc<RefCell<Vec<u64>>> = Rc::new(RefCell::new(Vec::new()));
 
     let scope: Rc<RefCell<Option<Scope>>> = Rc::new(RefCell::new(None));
 
+    let todo_list = {
+        let scope = scope.clone();
+        Rc::new(RefCell::new(KeyedList::try_new(
+            list,
+            |t: &Todo| t.id,
+            move |t| {
+                let scope = scope.borrow();
+                let scope = scope.as_ref().expect("the scope is set before any update");
+                Ok(todo_view(t, scope)?.into())
+            },
+            |node: &Node, t| {
+                node.unchecked_ref::<HtmlElement>()
+                    .set_class_name(if t.completed { "completed" } else { "" });
+                Ok(())
+            },
+        )))
+    };
+
     let update = {
-        let list = refs.remove("list").unwrap();
-        let count = refs.remove("count").unwrap();
         let todos = todos.clone();
-        let rendered_ids = rendered_ids.clone();
+        let todo_list = todo_list.clone();
         let filter = filter.clone();
-        let scope = scope.clone();
         let body_style = body.style();
         let clear_style = clear.style();
         let func = move || {
-            console::log_1(&JsValue::from(111));
-            let mut todos = todos.borrow_mut();
+            let todos = todos.borrow();
             let todos_count = todos.len();
             let completed_todos = todos.iter().filter(|t| t.completed).count();
             let uncompleted_todos = todos_count - completed_todos;
-            let mut visible_todos: Vec<_> = match *filter.borrow_mut() {
-                Filter::All => todos.iter_mut().collect(),
-                Filter::Active => todos.iter_mut().filter(|t| !t.completed).collect(),
-                Filter::Completed => todos.iter_mut().filter(|t| t.completed).collect(),
+            let mut visible_todos: Vec<Todo> = match *filter.borrow() {
+                Filter::All => todos.iter().cloned().collect(),
+                Filter::Active => todos.iter().filter(|t| !t.completed).cloned().collect(),
+                Filter::Completed => todos.iter().filter(|t| t.completed).cloned().collect(),
             };
 
             body_style
@@ -156,62 +202,38 @@ fn main_view(todos: Vec<Todo>) -> Result<stage0::Template, JsValue> {
                     if completed_todos > 0 { "block" } else { "none" },
                 )
                 .unwrap();
-            if todos_count > 0 {
-                let mut s = String::new();
-                s.push_str(uncompleted_todos.to_string().as_str());
-                s.push_str(" item");
-                if uncompleted_todos != 1 {
-                    s.push('s');
-                }
-                s.push_str(" left");
-                count.set_node_value(Some(&s));
-            } else {
-                count.set_node_value(None);
-            }
+            count.set_node_value(Some(&uncompleted_todos.to_string()));
+            plural.set_node_value(Some(if uncompleted_todos == 1 { "" } else { "s" }));
 
-            if let Some(scope) = scope.borrow().as_ref() {
-                console::log_1(&JsValue::from(222));
-                reconcile(
-                    &list,
-                    &rendered_ids.borrow(),
-                    &mut visible_todos,
-                    |t| t.id,
-                    move |t| todo_view(*t, scope).unwrap().into(),
-                    |node, item| {
-                        let update_node = Reflect::get(&node, &JsValue::from("update")).unwrap();
-                        if let Some(update_node) = update_node.dyn_ref::<js_sys::Function>() {
-                            update_node
-                                .call1(&JsValue::NULL, &JsValue::from_serde(item).unwrap())
-                                .unwrap();
-                            return;
-                        }
-                    },
-                    None,
-                    None,
-                );
-                *rendered_ids.borrow_mut() = visible_todos.iter().map(|t| t.id).collect();
-            } else {
-                console::log_1(&JsValue::from(333));
+            if let Err(err) = todo_list.borrow_mut().set_items(&mut visible_todos) {
+                console::error_1(&err.into());
             }
         };
         Rc::new(func)
     };
 
     *scope.borrow_mut() = Some(Scope {
-        render: {
+        toggle: {
             let update = update.clone();
-            let func = move || update();
-            Box::new(func)
+            let todos = todos.clone();
+            let func = move |id: u64, completed: bool| {
+                for t in todos.borrow_mut().iter_mut().filter(|t| t.id == id) {
+                    t.completed = completed;
+                }
+                update()
+            };
+            Rc::new(func)
         },
         delete: {
             let update = update.clone();
             let todos = todos.clone();
             let func = move |item: &Todo| {
-                todos.borrow_mut().retain(|t| t != item);
+                todos.borrow_mut().retain(|t| t.id != item.id);
                 update()
             };
-            Box::new(func)
+            Rc::new(func)
         },
+        todo_template: view(TODO_VIEW)?,
     });
 
     let create_todo = {
@@ -260,11 +282,7 @@ fn main_view(todos: Vec<Todo>) -> Result<stage0::Template, JsValue> {
     {
         let update = update.clone();
         let todos = todos.clone();
-        let toggleall = Rc::new(
-            refs.remove("toggleall")
-                .unwrap()
-                .unchecked_into::<HtmlInputElement>(),
-        );
+        let toggleall = Rc::new(toggleall);
         let onchange = {
             let toggleall = toggleall.clone();
             Closure::wrap(Box::new(move || {
@@ -281,7 +299,6 @@ fn main_view(todos: Vec<Todo>) -> Result<stage0::Template, JsValue> {
     }
 
     {
-        let all = refs.remove("all").unwrap().unchecked_into::<HtmlElement>();
         let filter = filter.clone();
         let update = update.clone();
         let onclick = Closure::wrap(Box::new(move || {
@@ -293,10 +310,6 @@ fn main_view(todos: Vec<Todo>) -> Result<stage0::Template, JsValue> {
     }
 
     {
-        let active = refs
-            .remove("active")
-            .unwrap()
-            .unchecked_into::<HtmlElement>();
         let filter = filter.clone();
         let update = update.clone();
         let onclick = Closure::wrap(Box::new(move || {
@@ -308,10 +321,6 @@ fn main_view(todos: Vec<Todo>) -> Result<stage0::Template, JsValue> {
     }
 
     {
-        let completed = refs
-            .remove("completed")
-            .unwrap()
-            .unchecked_into::<HtmlElement>();
         let filter = filter.clone();
         let update = update.clone();
         let onclick = Closure::wrap(Box::new(move || {
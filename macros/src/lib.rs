@@ -0,0 +1,196 @@
+extern crate proc_macro;
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use stage0_parser::{compile, CompileOptions, Namespace, Node};
+use syn::ext::IdentExt;
+use syn::parse::{Parse, ParseStream};
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Ident, LitBool, LitChar, LitStr, Token};
+
+struct HInput {
+    namespace: Namespace,
+    options: CompileOptions,
+    source: LitStr,
+}
+
+impl Parse for HInput {
+    fn parse(input: ParseStream) -> syn::Result<HInput> {
+        let mut namespace = Namespace::Html;
+        let mut options = CompileOptions::default();
+        while input.peek(Ident) {
+            let ident: Ident = input.parse()?;
+            match ident.to_string().as_str() {
+                "svg" => namespace = Namespace::Svg,
+                "math" => namespace = Namespace::MathMl,
+                "sigil" => {
+                    input.parse::<Token![=]>()?;
+                    options.sigil = input.parse::<LitChar>()?.value();
+                }
+                "collapse_whitespace" => {
+                    input.parse::<Token![=]>()?;
+                    options.collapse_whitespace = input.parse::<LitBool>()?.value;
+                }
+                _ => {
+                    return Err(syn::Error::new(
+                        ident.span(),
+                        "expected `svg`, `math`, `sigil = '...'` or `collapse_whitespace = ...`",
+                    ))
+                }
+            }
+            input.parse::<Token![,]>()?;
+        }
+        let source = input.parse()?;
+        Ok(HInput {
+            namespace,
+            options,
+            source,
+        })
+    }
+}
+
+fn node_tokens(node: &Node) -> TokenStream {
+    match node {
+        Node::Element(el) => {
+            let name = &el.name;
+            let attrs = el.attrs.iter().map(|(name, value)| quote!((#name, #value)));
+            let children = el.children.iter().map(node_tokens);
+            match el.namespace {
+                Namespace::Html => quote! {
+                    ::stage0::StaticNode::Element(#name, &[#(#attrs),*], &[#(#children),*])
+                },
+                namespace => {
+                    let uri = namespace.uri();
+                    quote! {
+                        ::stage0::StaticNode::ElementNs(
+                            #uri, #name, &[#(#attrs),*], &[#(#children),*]
+                        )
+                    }
+                }
+            }
+        }
+        Node::Text(value) => quote!(::stage0::StaticNode::Text(#value)),
+        Node::Comment(value) => quote!(::stage0::StaticNode::Comment(#value)),
+    }
+}
+
+fn expand(input: &HInput) -> Result<TokenStream, stage0_parser::Error> {
+    let (nodes, refs) = compile(&input.source.value(), input.namespace, &input.options)?;
+    let refs = refs.into_iter().map(|r| {
+        let path = r.path;
+        let attr = match r.attr {
+            Some(attr) => quote!(::std::option::Option::Some(#attr)),
+            None => quote!(::std::option::Option::None),
+        };
+        let name = r.name;
+        quote!((&[#(#path),*], #attr, #name))
+    });
+    let roots = nodes.iter().map(node_tokens);
+
+    Ok(quote! {
+        ::stage0::compile_static(&[#(#roots),*], &[#(#refs),*])
+    })
+}
+
+/// Parses a template at compile time.
+///
+/// `h!("<div #foo>#bar</div>")` behaves like `stage0::h` but reports malformed HTML and
+/// duplicate ref names as compile errors, and builds the DOM without walking it for refs.
+/// Elements inside `<svg>` and `<math>` are created in their namespace; prefix the template
+/// with `svg,` or `math,` to parse bare fragments such as `h!(svg, "<circle #dot/>")`.
+/// Write `##` for a literal `#`, or pick another sigil with `h!(sigil = '@', "<p @el></p>")`.
+/// `collapse_whitespace = true` collapses whitespace like `stage0::CompileOptions` does.
+#[proc_macro]
+pub fn h(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as HInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => syn::Error::new(input.source.span(), err)
+            .to_compile_error()
+            .into(),
+    }
+}
+
+fn ref_name(field: &syn::Field) -> syn::Result<String> {
+    let mut name = field
+        .ident
+        .as_ref()
+        .map(|ident| ident.unraw().to_string())
+        .unwrap_or_default();
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("refs"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = meta.value()?.parse::<LitStr>()?.value();
+                Ok(())
+            } else {
+                Err(meta.error("expected `name = \"...\"`"))
+            }
+        })?;
+    }
+    Ok(name)
+}
+
+fn expand_refs(input: &DeriveInput) -> syn::Result<TokenStream> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "`Refs` can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "`Refs` can only be derived for structs",
+            ))
+        }
+    };
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let inits = fields
+        .iter()
+        .map(|field| {
+            let field_ident = &field.ident;
+            let name = ref_name(field)?;
+            Ok(quote! {
+                #field_ident: ::stage0::__private::take_ref(&mut refs, #name)?
+            })
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    Ok(quote! {
+        impl #impl_generics ::stage0::Refs for #ident #ty_generics #where_clause {
+            fn from_refs(
+                mut refs: ::stage0::__private::HashMap<
+                    ::std::string::String,
+                    ::stage0::__private::Node,
+                >,
+            ) -> ::std::result::Result<Self, ::stage0::Stage0Error> {
+                ::std::result::Result::Ok(#ident {
+                    #(#inits,)*
+                })
+            }
+        }
+    })
+}
+
+/// Implements `stage0::Refs` for a struct whose fields are named after template refs.
+///
+/// Each field is taken from the collected refs and cast to its type, so a missing ref or
+/// a node of the wrong kind is reported as an error. Use `#[refs(name = "...")]` on a
+/// field when the ref name isn't a valid Rust identifier.
+#[proc_macro_derive(Refs, attributes(refs))]
+pub fn derive_refs(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand_refs(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
@@ -0,0 +1,550 @@
+mod refs;
+mod render;
+mod whitespace;
+
+pub use refs::{
+    attr_collector, check_sigil, collector, find_text_ref, gen_path, is_ref_name, marker_ref,
+    unescape, Ref,
+};
+pub use render::{render, HtmlTemplate};
+pub use whitespace::{
+    collapse_text, collapse_whitespace, is_block, BLOCK_ELEMENTS, PREFORMATTED_ELEMENTS,
+};
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Element(Element),
+    Text(String),
+    Comment(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Namespace {
+    Html,
+    Svg,
+    MathMl,
+}
+
+impl Namespace {
+    pub fn uri(self) -> &'static str {
+        match self {
+            Namespace::Html => "http://www.w3.org/1999/xhtml",
+            Namespace::Svg => "http://www.w3.org/2000/svg",
+            Namespace::MathMl => "http://www.w3.org/1998/Math/MathML",
+        }
+    }
+}
+
+/// Options for compiling a template, shared by `h!`, `HtmlTemplate` and the runtime
+/// `compile_with` so that they all agree on where the refs are.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CompileOptions {
+    /// The character that marks refs, `#` by default. Doubling it escapes a literal one,
+    /// so `##1 priority` renders as `#1 priority`.
+    pub sigil: char,
+    /// Drops whitespace-only text around block elements and collapses other runs of
+    /// whitespace to a single space, leaving `<pre>`, `<textarea>`, `<script>` and
+    /// `<style>` alone. Only ASCII whitespace collapses, so `&nbsp;` is kept. Off by default.
+    pub collapse_whitespace: bool,
+}
+
+impl Default for CompileOptions {
+    fn default() -> CompileOptions {
+        CompileOptions {
+            sigil: '#',
+            collapse_whitespace: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Element {
+    pub namespace: Namespace,
+    pub name: String,
+    pub attrs: Vec<(String, String)>,
+    pub children: Vec<Node>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    message: String,
+}
+
+impl Error {
+    pub fn new(message: String) -> Error {
+        Error { message }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// Elements whose text is kept verbatim, never parsed for markup or refs.
+pub const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style"];
+
+/// Elements whose text is parsed for refs but can't hold markup, so a ref there has to be
+/// the element's whole text.
+pub const ESCAPABLE_RAW_TEXT_ELEMENTS: &[&str] = &["textarea", "title"];
+
+fn is_void(name: &str) -> bool {
+    VOID_ELEMENTS.contains(&name)
+}
+
+fn is_tag_start(s: &str) -> bool {
+    let mut chars = s.chars();
+    if chars.next() != Some('<') {
+        return false;
+    }
+    match chars.next() {
+        Some('!') | Some('?') => true,
+        Some('/') => chars.next().is_some_and(|c| c.is_ascii_alphabetic()),
+        Some(c) => c.is_ascii_alphabetic(),
+        None => false,
+    }
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    if let Some(hex) = entity
+        .strip_prefix("#x")
+        .or_else(|| entity.strip_prefix("#X"))
+    {
+        return u32::from_str_radix(hex, 16)
+            .ok()
+            .and_then(std::char::from_u32);
+    }
+    if let Some(dec) = entity.strip_prefix('#') {
+        return dec.parse().ok().and_then(std::char::from_u32);
+    }
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some('\u{a0}'),
+        _ => None,
+    }
+}
+
+fn decode(raw: &str, offset: usize) -> Result<String, Error> {
+    let mut out = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let end = rest[1..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '#'))
+            .map_or(rest.len(), |i| i + 1);
+        if end > 1 && rest[end..].starts_with(';') {
+            let entity = &rest[1..end];
+            match decode_entity(entity) {
+                Some(c) => {
+                    out.push(c);
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+                None => {
+                    return Err(Error::new(format!(
+                        "unknown character reference `&{};` at byte {}",
+                        entity,
+                        offset + raw.len() - rest.len()
+                    )));
+                }
+            }
+        }
+        out.push('&');
+        rest = &rest[1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.source[self.pos..]
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, Error> {
+        Err(Error::new(format!("{} at byte {}", message, self.pos)))
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn take_until(&mut self, stop: impl Fn(char) -> bool) -> &'a str {
+        let rest = self.rest();
+        let end = rest.find(stop).unwrap_or(rest.len());
+        self.pos += end;
+        &rest[..end]
+    }
+
+    fn parse_nodes(
+        &mut self,
+        parent: Option<&str>,
+        namespace: Namespace,
+    ) -> Result<Vec<Node>, Error> {
+        let mut nodes = Vec::new();
+        loop {
+            let rest = self.rest();
+            if rest.is_empty() {
+                return match parent {
+                    Some(name) => self.error(format!("unclosed `<{}>`", name)),
+                    None => Ok(nodes),
+                };
+            }
+
+            if rest.starts_with("</") && is_tag_start(rest) {
+                let start = self.pos;
+                self.pos += 2;
+                let name = self.take_until(|c| c.is_whitespace() || c == '>');
+                self.skip_whitespace();
+                if !self.rest().starts_with('>') {
+                    return self.error(format!("expected `>` to close `</{}`", name));
+                }
+                self.pos += 1;
+                return match parent {
+                    Some(open) if open.eq_ignore_ascii_case(name) => Ok(nodes),
+                    Some(open) => Err(Error::new(format!(
+                        "expected `</{}>`, found `</{}>` at byte {}",
+                        open, name, start
+                    ))),
+                    None => Err(Error::new(format!(
+                        "unexpected `</{}>` at byte {}",
+                        name, start
+                    ))),
+                };
+            }
+
+            if rest.starts_with("<!--") {
+                self.pos += 4;
+                match self.rest().find("-->") {
+                    Some(end) => {
+                        nodes.push(Node::Comment(self.rest()[..end].to_owned()));
+                        self.pos += end + 3;
+                    }
+                    None => return self.error("unterminated comment".to_owned()),
+                }
+            } else if rest.starts_with("<!") || rest.starts_with("<?") {
+                return self.error("unsupported markup declaration".to_owned());
+            } else if is_tag_start(rest) {
+                nodes.push(Node::Element(self.parse_element(namespace)?));
+            } else {
+                let start = self.pos;
+                let mut end = rest.chars().next().map_or(1, char::len_utf8);
+                while end < rest.len() && !is_tag_start(&rest[end..]) {
+                    end += rest[end..].chars().next().map_or(1, char::len_utf8);
+                }
+                self.pos += end;
+                nodes.push(Node::Text(decode(&rest[..end], start)?));
+            }
+        }
+    }
+
+    fn parse_element(&mut self, parent_namespace: Namespace) -> Result<Element, Error> {
+        let start = self.pos;
+        self.pos += 1;
+        let raw_name = self.take_until(|c| c.is_whitespace() || c == '/' || c == '>');
+        let lower_name = raw_name.to_ascii_lowercase();
+        // Like the HTML parser, `<svg>` and `<math>` switch to foreign content, where names
+        // are case-sensitive and any element may be self-closing.
+        let namespace = match (parent_namespace, lower_name.as_str()) {
+            (Namespace::Html, "svg") => Namespace::Svg,
+            (Namespace::Html, "math") => Namespace::MathMl,
+            (namespace, _) => namespace,
+        };
+        let (name, children_namespace) = match namespace {
+            Namespace::Html => (lower_name, Namespace::Html),
+            Namespace::Svg if lower_name == "foreignobject" => {
+                (raw_name.to_owned(), Namespace::Html)
+            }
+            _ => (raw_name.to_owned(), namespace),
+        };
+        let is_void = namespace == Namespace::Html && is_void(&name);
+        let mut attrs: Vec<(String, String)> = Vec::new();
+
+        loop {
+            self.skip_whitespace();
+            let rest = self.rest();
+
+            if rest.starts_with("/>") {
+                self.pos += 2;
+                if namespace == Namespace::Html && !is_void {
+                    return Err(Error::new(format!(
+                        "`<{}/>` at byte {} is not a void element and must be closed with `</{}>`",
+                        name, start, name
+                    )));
+                }
+                return Ok(Element {
+                    namespace,
+                    name,
+                    attrs,
+                    children: Vec::new(),
+                });
+            }
+
+            if rest.starts_with('>') {
+                self.pos += 1;
+                let children = if is_void {
+                    Vec::new()
+                } else if namespace == Namespace::Html
+                    && (RAW_TEXT_ELEMENTS.contains(&name.as_str())
+                        || ESCAPABLE_RAW_TEXT_ELEMENTS.contains(&name.as_str()))
+                {
+                    self.parse_raw_text(&name)?
+                } else {
+                    self.parse_nodes(Some(&name), children_namespace)?
+                };
+                return Ok(Element {
+                    namespace,
+                    name,
+                    attrs,
+                    children,
+                });
+            }
+
+            if rest.is_empty() {
+                return self.error(format!("unterminated `<{}` tag", name));
+            }
+
+            let attr_start = self.pos;
+            let mut attr_name = self
+                .take_until(|c| c.is_whitespace() || c == '=' || c == '>' || c == '/')
+                .to_owned();
+            if namespace == Namespace::Html {
+                attr_name.make_ascii_lowercase();
+            }
+            if attr_name.is_empty() {
+                return self.error(format!("expected attribute name in `<{}>`", name));
+            }
+            self.skip_whitespace();
+            let value = if self.rest().starts_with('=') {
+                self.pos += 1;
+                self.skip_whitespace();
+                self.parse_attr_value()?
+            } else {
+                String::new()
+            };
+            if attrs.iter().any(|(n, _)| *n == attr_name) {
+                return Err(Error::new(format!(
+                    "duplicate attribute `{}` at byte {}",
+                    attr_name, attr_start
+                )));
+            }
+            attrs.push((attr_name, value));
+        }
+    }
+
+    fn parse_attr_value(&mut self) -> Result<String, Error> {
+        let rest = self.rest();
+        match rest.chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => match rest[1..].find(quote) {
+                Some(end) => {
+                    let value = decode(&rest[1..=end], self.pos + 1)?;
+                    self.pos += end + 2;
+                    Ok(value)
+                }
+                None => self.error("unterminated attribute value".to_owned()),
+            },
+            _ => {
+                let start = self.pos;
+                let raw = self.take_until(|c| c.is_whitespace() || c == '>');
+                decode(raw, start)
+            }
+        }
+    }
+
+    fn parse_raw_text(&mut self, name: &str) -> Result<Vec<Node>, Error> {
+        let start = self.pos;
+        let rest = self.rest();
+        let close = format!("</{}", name);
+        let end = match rest.to_ascii_lowercase().find(&close) {
+            Some(end) => end,
+            None => return self.error(format!("unclosed `<{}>`", name)),
+        };
+        self.pos += end + close.len();
+        self.skip_whitespace();
+        if !self.rest().starts_with('>') {
+            return self.error(format!("expected `>` to close `</{}`", name));
+        }
+        self.pos += 1;
+
+        let raw = &rest[..end];
+        if raw.is_empty() {
+            Ok(Vec::new())
+        } else if RAW_TEXT_ELEMENTS.contains(&name) {
+            Ok(vec![Node::Text(raw.to_owned())])
+        } else {
+            Ok(vec![Node::Text(decode(raw, start)?)])
+        }
+    }
+}
+
+/// Parses `source` as the children of an element in `namespace`, so an SVG fragment
+/// without its `<svg>` root can be parsed with `Namespace::Svg`.
+pub fn parse(source: &str, namespace: Namespace) -> Result<Vec<Node>, Error> {
+    let mut parser = Parser { source, pos: 0 };
+    parser.parse_nodes(None, namespace)
+}
+
+/// Parses `source` and collects its refs with `options`, giving the same nodes and ref
+/// paths as the runtime `compile_with` does on the DOM parsed from `source`.
+pub fn compile(
+    source: &str,
+    namespace: Namespace,
+    options: &CompileOptions,
+) -> Result<(Vec<Node>, Vec<Ref>), Error> {
+    let mut roots = parse(source.trim(), namespace)?;
+    if options.collapse_whitespace {
+        collapse_whitespace(&mut roots);
+    }
+    let refs = gen_path(&mut roots, options.sigil)?;
+    Ok((roots, refs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Element, Namespace, Node};
+
+    fn parse(source: &str) -> Result<Vec<Node>, super::Error> {
+        super::parse(source, Namespace::Html)
+    }
+
+    fn el(name: &str, attrs: &[(&str, &str)], children: Vec<Node>) -> Node {
+        ns_el(Namespace::Html, name, attrs, children)
+    }
+
+    fn ns_el(
+        namespace: Namespace,
+        name: &str,
+        attrs: &[(&str, &str)],
+        children: Vec<Node>,
+    ) -> Node {
+        Node::Element(Element {
+            namespace,
+            name: name.to_owned(),
+            attrs: attrs
+                .iter()
+                .map(|&(n, v)| (n.to_owned(), v.to_owned()))
+                .collect(),
+            children,
+        })
+    }
+
+    fn text(value: &str) -> Node {
+        Node::Text(value.to_owned())
+    }
+
+    #[test]
+    fn parse_tests() {
+        assert_eq!(parse("<div></div>"), Ok(vec![el("div", &[], vec![])]));
+
+        assert_eq!(
+            parse("<DIV #foo Class='a &amp; b'>#bar</DIV>"),
+            Ok(vec![el(
+                "div",
+                &[("#foo", ""), ("class", "a & b")],
+                vec![text("#bar")]
+            )])
+        );
+
+        assert_eq!(
+            parse("<p>a < b<br><input type=checkbox /><!-- c --></p>"),
+            Ok(vec![el(
+                "p",
+                &[],
+                vec![
+                    text("a < b"),
+                    el("br", &[], vec![]),
+                    el("input", &[("type", "checkbox")], vec![]),
+                    Node::Comment(" c ".to_owned()),
+                ]
+            )])
+        );
+
+        assert_eq!(
+            parse("<style>a > b {}</style>"),
+            Ok(vec![el("style", &[], vec![text("a > b {}")])])
+        );
+
+        assert_eq!(
+            parse("<p>\u{e9}t\u{e9}<b>\u{a0}</b></p>"),
+            Ok(vec![el(
+                "p",
+                &[],
+                vec![text("\u{e9}t\u{e9}"), el("b", &[], vec![text("\u{a0}")])]
+            )])
+        );
+    }
+
+    #[test]
+    fn parse_namespace_tests() {
+        assert_eq!(
+            parse("<svg viewBox='0 0 1 1'><circle #dot/><foreignObject><p>hi</p></foreignObject></svg>"),
+            Ok(vec![ns_el(
+                Namespace::Svg,
+                "svg",
+                &[("viewBox", "0 0 1 1")],
+                vec![
+                    ns_el(Namespace::Svg, "circle", &[("#dot", "")], vec![]),
+                    ns_el(
+                        Namespace::Svg,
+                        "foreignObject",
+                        &[],
+                        vec![el("p", &[], vec![text("hi")])]
+                    ),
+                ]
+            )])
+        );
+
+        assert_eq!(
+            super::parse(
+                "<path d='M0 0'/><linearGradient></linearGradient>",
+                Namespace::Svg
+            ),
+            Ok(vec![
+                ns_el(Namespace::Svg, "path", &[("d", "M0 0")], vec![]),
+                ns_el(Namespace::Svg, "linearGradient", &[], vec![]),
+            ])
+        );
+
+        assert_eq!(
+            parse("<math><mi>x</mi></math>"),
+            Ok(vec![ns_el(
+                Namespace::MathMl,
+                "math",
+                &[],
+                vec![ns_el(Namespace::MathMl, "mi", &[], vec![text("x")])]
+            )])
+        );
+    }
+
+    #[test]
+    fn parse_error_tests() {
+        assert!(parse("<div>").is_err());
+        assert!(parse("<div></span>").is_err());
+        assert!(parse("</div>").is_err());
+        assert!(parse("<div/>").is_err());
+        assert!(parse("<div a a></div>").is_err());
+        assert!(parse("<div>&bogus;</div>").is_err());
+        assert!(parse("<!DOCTYPE html>").is_err());
+    }
+}
@@ -0,0 +1,394 @@
+use super::{Element, Error, Node, ESCAPABLE_RAW_TEXT_ELEMENTS, RAW_TEXT_ELEMENTS};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ref {
+    pub path: Vec<u32>,
+    pub attr: Option<String>,
+    pub name: String,
+}
+
+/// Whether `name` can follow the sigil as a ref name.
+pub fn is_ref_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// The ref name of a `#name` marker attribute, or `None` if `attr` isn't a marker. A
+/// marker that isn't followed by a valid ref name, such as `##a` or `#a.b`, is an error
+/// rather than a ref with a surprising name.
+pub fn marker_ref(attr: &str, sigil: char) -> Result<Option<&str>, Error> {
+    match attr.strip_prefix(sigil) {
+        None => Ok(None),
+        Some(name) if is_ref_name(name) => Ok(Some(name)),
+        Some(_) => Err(Error::new(format!("`{}` isn't a valid ref marker", attr))),
+    }
+}
+
+/// Collects `attr="#name"` bindings on `el` and blanks their values, like the runtime
+/// `attr_collector`. A leading `##` is unescaped to a literal `#`.
+pub fn attr_collector(el: &mut Element, sigil: char) -> Vec<(String, String)> {
+    let mut bindings = Vec::new();
+    for (attr, value) in el.attrs.iter_mut() {
+        match value.strip_prefix(sigil) {
+            Some(rest) if rest.starts_with(sigil) => *value = rest.to_owned(),
+            Some(name) if is_ref_name(name) => {
+                bindings.push((attr.clone(), name.to_owned()));
+                value.clear();
+            }
+            _ => {}
+        }
+    }
+    bindings
+}
+
+/// Finds the first `#name` placeholder in a text value, returning its byte offset.
+/// Escaped `##` pairs are skipped.
+pub fn find_text_ref(value: &str, sigil: char) -> Option<(usize, &str)> {
+    let mut chars = value.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if c != sigil {
+            continue;
+        }
+        if chars.peek().map(|&(_, next)| next) == Some(sigil) {
+            chars.next();
+            continue;
+        }
+        let rest = &value[start + sigil.len_utf8()..];
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+            .unwrap_or(rest.len());
+        if len > 0 {
+            return Some((start, &rest[..len]));
+        }
+    }
+    None
+}
+
+/// Collapses escaped `##` pairs into a literal `#`, or returns `None` if there are none.
+pub fn unescape(value: &str, sigil: char) -> Option<String> {
+    let escape: String = [sigil, sigil].iter().collect();
+    if value.contains(&escape) {
+        Some(value.replace(&escape, &sigil.to_string()))
+    } else {
+        None
+    }
+}
+
+// Splits a `#name` placeholder off into its own text node like the runtime `collector`,
+// returning the remainder to be inserted as the next sibling.
+fn split_text(node: &mut Node, sigil: char) -> Option<Node> {
+    if let Node::Text(value) = node {
+        if let Some((start, name)) = find_text_ref(value, sigil) {
+            let end = start + sigil.len_utf8() + name.len();
+            let split_at = if start > 0 { start } else { end };
+            if split_at < value.len() {
+                return Some(Node::Text(value.split_off(split_at)));
+            }
+        }
+    }
+    None
+}
+
+/// Checks that `sigil` can mark refs: it can't be whitespace, `/`, which closes anchors,
+/// or a character that may appear in a ref name.
+pub fn check_sigil(sigil: char) -> Result<(), Error> {
+    if sigil.is_whitespace() || sigil == '/' || is_ref_name(&sigil.to_string()) {
+        return Err(Error::new(format!("`{}` can't be used as a sigil", sigil)));
+    }
+    Ok(())
+}
+
+/// Strips the `#name` marker from `node` and returns the ref name, like the runtime
+/// `collector` does on a live DOM node.
+pub fn collector(node: &mut Node, sigil: char) -> Result<Option<String>, Error> {
+    match node {
+        Node::Element(el) => {
+            let mut marker = None;
+            for (i, (attr, _)) in el.attrs.iter().enumerate() {
+                if let Some(name) = marker_ref(attr, sigil)? {
+                    if marker.is_some() {
+                        return Err(Error::new(format!("`<{}>` has more than one ref", el.name)));
+                    }
+                    marker = Some((i, name.to_owned()));
+                }
+            }
+            Ok(marker.map(|(i, name)| {
+                el.attrs.remove(i);
+                name
+            }))
+        }
+        Node::Comment(value) => match value.trim().strip_prefix(sigil) {
+            Some(name) if is_ref_name(name) => {
+                let name = name.to_owned();
+                *value = name.clone();
+                Ok(Some(name))
+            }
+            _ => Ok(None),
+        },
+        Node::Text(value) => match find_text_ref(value, sigil) {
+            Some((0, name)) if sigil.len_utf8() + name.len() == value.len() => {
+                let name = name.to_owned();
+                value.clear();
+                Ok(Some(name))
+            }
+            _ => {
+                if let Some(literal) = unescape(value, sigil) {
+                    *value = literal;
+                }
+                Ok(None)
+            }
+        },
+    }
+}
+
+fn push_ref(
+    indices: &mut Vec<Ref>,
+    path: &[u32],
+    attr: Option<String>,
+    name: String,
+) -> Result<(), Error> {
+    if name.is_empty() {
+        return Err(Error::new("empty ref name".to_owned()));
+    }
+    if indices.iter().any(|r| r.name == name) {
+        return Err(Error::new(format!("duplicate ref `#{}`", name)));
+    }
+    indices.push(Ref {
+        path: path.to_vec(),
+        attr,
+        name,
+    });
+    Ok(())
+}
+
+fn walk(
+    node: &mut Node,
+    sigil: char,
+    path: &mut Vec<u32>,
+    indices: &mut Vec<Ref>,
+) -> Result<(), Error> {
+    if let Some(name) = collector(node, sigil)? {
+        push_ref(indices, path, None, name)?;
+    }
+
+    if let Node::Element(el) = node {
+        for (attr, name) in attr_collector(el, sigil) {
+            push_ref(indices, path, Some(attr), name)?;
+        }
+        // Script and style contents are left alone, so `color: #fff` isn't a ref.
+        if !RAW_TEXT_ELEMENTS.contains(&el.name.as_str()) {
+            let texts = count_texts(&el.children);
+            walk_children(&mut el.children, sigil, path, indices)?;
+            if ESCAPABLE_RAW_TEXT_ELEMENTS.contains(&el.name.as_str())
+                && count_texts(&el.children) > texts
+            {
+                return Err(Error::new(format!(
+                    "a ref in `<{}>` has to be its whole text",
+                    el.name
+                )));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn count_texts(nodes: &[Node]) -> usize {
+    nodes
+        .iter()
+        .filter(|node| matches!(node, Node::Text(_)))
+        .count()
+}
+
+fn walk_children(
+    nodes: &mut Vec<Node>,
+    sigil: char,
+    path: &mut Vec<u32>,
+    indices: &mut Vec<Ref>,
+) -> Result<(), Error> {
+    let mut idx = 0;
+    while idx < nodes.len() {
+        if let Some(rest) = split_text(&mut nodes[idx], sigil) {
+            nodes.insert(idx + 1, rest);
+        }
+        path.push(idx as u32);
+        let count = indices.len();
+        walk(&mut nodes[idx], sigil, path, indices)?;
+        path.pop();
+
+        // Close `<!--#name-->` anchors with a `<!--/name-->` comment, like the runtime
+        // `insert_anchor_end`.
+        if let Node::Comment(name) = &nodes[idx] {
+            if indices.len() > count {
+                let end = Node::Comment(format!("/{}", name));
+                nodes.insert(idx + 1, end);
+            }
+        }
+        idx += 1;
+    }
+    Ok(())
+}
+
+/// Collects the refs of a template in document order, each as the path of child
+/// indices leading to it, exactly like the runtime `gen_path`. A single root is the
+/// template node itself while several roots are wrapped in a fragment. `sigil` marks
+/// refs, and doubling it escapes a literal one.
+pub fn gen_path(roots: &mut Vec<Node>, sigil: char) -> Result<Vec<Ref>, Error> {
+    check_sigil(sigil)?;
+    let mut indices = Vec::new();
+    match roots.as_mut_slice() {
+        [] => return Err(Error::new("empty template".to_owned())),
+        [Node::Comment(value)] if value.trim().strip_prefix(sigil).is_some_and(is_ref_name) => {
+            return Err(Error::new(format!(
+                "anchor `{}` can't be the only root node",
+                value.trim()
+            )));
+        }
+        // A lone text root is kept in a fragment so interpolation can split it.
+        [root] if !matches!(root, Node::Text(_)) => {
+            walk(root, sigil, &mut Vec::new(), &mut indices)?
+        }
+        _ => walk_children(roots, sigil, &mut Vec::new(), &mut indices)?,
+    }
+    Ok(indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{gen_path, Ref};
+    use crate::{parse, Namespace, Node};
+
+    fn refs(source: &str) -> Result<Vec<Ref>, crate::Error> {
+        let mut nodes = parse(source, Namespace::Html)?;
+        gen_path(&mut nodes, '#')
+    }
+
+    fn r(path: &[u32], name: &str) -> Ref {
+        Ref {
+            path: path.to_vec(),
+            attr: None,
+            name: name.to_owned(),
+        }
+    }
+
+    #[test]
+    fn gen_path_tests() {
+        assert_eq!(refs("<div></div>"), Ok(vec![]));
+        assert_eq!(refs("<div #test></div>"), Ok(vec![r(&[], "test")]));
+        assert_eq!(
+            refs("<div #foo>#bar</div>"),
+            Ok(vec![r(&[], "foo"), r(&[0], "bar")])
+        );
+        assert_eq!(
+            refs("<div> <h1>#count</h1> <button #down>-</button></div>"),
+            Ok(vec![r(&[1, 0], "count"), r(&[3], "down")])
+        );
+
+        assert_eq!(
+            refs("<a #link href='#href' data-nav='#/active'><b class='#cls'></b></a>"),
+            Ok(vec![
+                r(&[], "link"),
+                Ref {
+                    attr: Some("href".to_owned()),
+                    ..r(&[], "href")
+                },
+                Ref {
+                    attr: Some("class".to_owned()),
+                    ..r(&[0], "cls")
+                },
+            ])
+        );
+
+        assert_eq!(
+            refs("<ul><li></li><!-- #items --><li #last></li></ul>"),
+            Ok(vec![r(&[1], "items"), r(&[3], "last")])
+        );
+        assert_eq!(
+            refs("<!--#rows--><p #footer></p>"),
+            Ok(vec![r(&[0], "rows"), r(&[2], "footer")])
+        );
+        assert!(refs("<!--#alone-->").is_err());
+
+        assert!(refs("<div #a><span #a></span></div>").is_err());
+        assert!(refs("<div #a><span title='#a'></span></div>").is_err());
+        assert!(refs("<div #a #b></div>").is_err());
+        assert!(refs("<div #></div>").is_err());
+        assert!(refs("").is_err());
+
+        assert_eq!(refs("#a<b #c></b>"), Ok(vec![r(&[0], "a"), r(&[1], "c")]));
+        assert_eq!(refs("#a"), Ok(vec![r(&[0], "a")]));
+    }
+
+    #[test]
+    fn text_interpolation_tests() {
+        let mut nodes = parse("<p>Hello #name! #a#b</p>", Namespace::Html).unwrap();
+        assert_eq!(
+            gen_path(&mut nodes, '#'),
+            Ok(vec![r(&[1], "name"), r(&[3], "a"), r(&[4], "b")])
+        );
+        let text = |value: &str| Node::Text(value.to_owned());
+        match &nodes[0] {
+            Node::Element(el) => assert_eq!(
+                el.children,
+                vec![text("Hello "), text(""), text("! "), text(""), text("")]
+            ),
+            _ => unreachable!(),
+        }
+
+        assert_eq!(refs("<p>Price # 5</p>"), Ok(vec![]));
+
+        let mut nodes = parse(
+            "<style>.a{color:#fff}.b{color:#000}</style><script>x = #y</script>",
+            Namespace::Html,
+        )
+        .unwrap();
+        assert_eq!(gen_path(&mut nodes, '#'), Ok(vec![]));
+        match &nodes[0] {
+            Node::Element(el) => {
+                assert_eq!(el.children, vec![text(".a{color:#fff}.b{color:#000}")])
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn escape_tests() {
+        let text = |value: &str| Node::Text(value.to_owned());
+
+        let mut nodes = parse("<p title='##tag'>##1 priority, ###n</p>", Namespace::Html).unwrap();
+        assert_eq!(gen_path(&mut nodes, '#'), Ok(vec![r(&[1], "n")]));
+        match &nodes[0] {
+            Node::Element(el) => {
+                assert_eq!(el.attrs, vec![("title".to_owned(), "#tag".to_owned())]);
+                assert_eq!(el.children, vec![text("#1 priority, #"), text("")]);
+            }
+            _ => unreachable!(),
+        }
+
+        let mut nodes = parse("<a @link href='#/active'>@@x #y @z</a>", Namespace::Html).unwrap();
+        assert_eq!(
+            gen_path(&mut nodes, '@'),
+            Ok(vec![r(&[], "link"), r(&[1], "z")])
+        );
+        assert!(gen_path(&mut nodes, 'a').is_err());
+        for source in ["<div ##a></div>", "<div #a.b></div>", "<div #></div>"].iter() {
+            assert!(refs(source).is_err(), "{}", source);
+        }
+        assert!(refs("<textarea>Hello #name</textarea>").is_err());
+        assert!(refs("<title>#a#b</title>").is_err());
+        assert_eq!(
+            refs("<textarea>#value</textarea><title>#title</title>"),
+            Ok(vec![r(&[0, 0], "value"), r(&[1, 0], "title")])
+        );
+        assert!(gen_path(&mut nodes, '/').is_err());
+        match &nodes[0] {
+            Node::Element(el) => {
+                assert_eq!(el.attrs, vec![("href".to_owned(), "#/active".to_owned())]);
+                assert_eq!(el.children, vec![text("@x #y "), text("")]);
+            }
+            _ => unreachable!(),
+        }
+    }
+}
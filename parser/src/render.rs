@@ -0,0 +1,311 @@
+use super::{
+    compile, is_void, CompileOptions, Element, Error, Namespace, Node, Ref,
+    ESCAPABLE_RAW_TEXT_ELEMENTS, RAW_TEXT_ELEMENTS,
+};
+use std::collections::HashMap;
+
+/// A template parsed once and rendered to HTML strings without a DOM, for pre-rendering
+/// pages on the server.
+#[derive(Debug, Clone)]
+pub struct HtmlTemplate {
+    roots: Vec<Node>,
+    refs: Vec<Ref>,
+}
+
+// A value bound to a ref, looked up by the path of the node it belongs to.
+struct Binding<'a> {
+    attr: Option<&'a str>,
+    value: &'a str,
+}
+
+impl HtmlTemplate {
+    pub fn parse(source: &str) -> Result<HtmlTemplate, Error> {
+        HtmlTemplate::parse_ns(source, Namespace::Html)
+    }
+
+    /// Like `parse`, but parses `source` as the children of an `<svg>` or `<math>` element.
+    pub fn parse_ns(source: &str, namespace: Namespace) -> Result<HtmlTemplate, Error> {
+        HtmlTemplate::parse_with(source, namespace, &CompileOptions::default())
+    }
+
+    /// Like `parse_ns`, but compiles the template with `options`. Pass the same options
+    /// to the runtime `compile_with` or `h!` so the rendered page can be hydrated.
+    pub fn parse_with(
+        source: &str,
+        namespace: Namespace,
+        options: &CompileOptions,
+    ) -> Result<HtmlTemplate, Error> {
+        let (roots, refs) = compile(source, namespace, options)?;
+        Ok(HtmlTemplate { roots, refs })
+    }
+
+    /// The ref names in document order.
+    pub fn ref_names(&self) -> impl Iterator<Item = &str> {
+        self.refs.iter().map(|r| r.name.as_str())
+    }
+
+    /// Renders the template with `values` filling in its refs. Text and attribute values
+    /// are escaped, while an anchor's value is inserted as is between its comments so
+    /// the output of another `render` can be nested. Refs without a value render empty,
+    /// and adjacent text nodes are separated by `<!---->` so they survive reparsing.
+    pub fn render<I, K, V>(&self, values: I) -> Result<String, Error>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let values: Vec<(K, V)> = values.into_iter().collect();
+        let mut bindings: HashMap<&[u32], Vec<Binding>> = HashMap::new();
+        for (name, value) in values.iter() {
+            let name = name.as_ref();
+            let r = self
+                .refs
+                .iter()
+                .find(|r| r.name == name)
+                .ok_or_else(|| Error::new(format!("unknown ref `#{}`", name)))?;
+            bindings.entry(&r.path).or_default().push(Binding {
+                attr: r.attr.as_deref(),
+                value: value.as_ref(),
+            });
+        }
+
+        let mut html = String::new();
+        let mut path = Vec::new();
+        match self.roots.as_slice() {
+            [root] if !matches!(root, Node::Text(_)) => {
+                render_node(root, &mut path, &bindings, &mut html)?
+            }
+            roots => render_children(roots, false, true, &mut path, &bindings, &mut html)?,
+        }
+        Ok(html)
+    }
+}
+
+/// Parses `source` and renders it with `values`, see `HtmlTemplate::render`.
+pub fn render<I, K, V>(source: &str, values: I) -> Result<String, Error>
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    HtmlTemplate::parse(source)?.render(values)
+}
+
+fn escape_text(value: &str, html: &mut String) {
+    for c in value.chars() {
+        match c {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            c => html.push(c),
+        }
+    }
+}
+
+fn escape_attr(value: &str, html: &mut String) {
+    for c in value.chars() {
+        match c {
+            '&' => html.push_str("&amp;"),
+            '"' => html.push_str("&quot;"),
+            c => html.push(c),
+        }
+    }
+}
+
+fn render_children(
+    nodes: &[Node],
+    raw: bool,
+    separators: bool,
+    path: &mut Vec<u32>,
+    bindings: &HashMap<&[u32], Vec<Binding>>,
+    html: &mut String,
+) -> Result<(), Error> {
+    for (idx, node) in nodes.iter().enumerate() {
+        path.push(idx as u32);
+        match node {
+            Node::Text(value) if raw => html.push_str(value),
+            node => {
+                if let (true, Node::Text(_), Some(Node::Text(_))) =
+                    (separators, node, idx.checked_sub(1).map(|i| &nodes[i]))
+                {
+                    html.push_str("<!---->");
+                }
+                render_node(node, path, bindings, html)?;
+            }
+        }
+        path.pop();
+    }
+    Ok(())
+}
+
+fn render_node(
+    node: &Node,
+    path: &mut Vec<u32>,
+    bindings: &HashMap<&[u32], Vec<Binding>>,
+    html: &mut String,
+) -> Result<(), Error> {
+    let bound = bindings.get(path.as_slice()).map_or(&[][..], Vec::as_slice);
+    let node_value = bound.iter().find(|b| b.attr.is_none()).map(|b| b.value);
+    match node {
+        Node::Text(value) => escape_text(node_value.unwrap_or(value), html),
+        Node::Comment(value) => {
+            html.push_str("<!--");
+            html.push_str(value);
+            html.push_str("-->");
+            if let Some(value) = node_value {
+                html.push_str(value);
+            }
+        }
+        Node::Element(el) => {
+            if node_value.is_some() {
+                return Err(Error::new(format!(
+                    "element ref `<{}>` can't take a value",
+                    el.name
+                )));
+            }
+            render_element(el, bound, path, bindings, html)?;
+        }
+    }
+    Ok(())
+}
+
+fn render_element(
+    el: &Element,
+    bound: &[Binding],
+    path: &mut Vec<u32>,
+    bindings: &HashMap<&[u32], Vec<Binding>>,
+    html: &mut String,
+) -> Result<(), Error> {
+    html.push('<');
+    html.push_str(&el.name);
+    for (name, value) in el.attrs.iter() {
+        let value = bound
+            .iter()
+            .find(|b| b.attr == Some(name.as_str()))
+            .map_or(value.as_str(), |b| b.value);
+        html.push(' ');
+        html.push_str(name);
+        html.push_str("=\"");
+        escape_attr(value, html);
+        html.push('"');
+    }
+    html.push('>');
+
+    if el.namespace == Namespace::Html && is_void(&el.name) {
+        return Ok(());
+    }
+    let raw = el.namespace == Namespace::Html && RAW_TEXT_ELEMENTS.contains(&el.name.as_str());
+    // A comment inside `<textarea>` or `<title>` would show up as literal text.
+    let separators = !(el.namespace == Namespace::Html
+        && ESCAPABLE_RAW_TEXT_ELEMENTS.contains(&el.name.as_str()));
+    render_children(&el.children, raw, separators, path, bindings, html)?;
+    html.push_str("</");
+    html.push_str(&el.name);
+    html.push('>');
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render, HtmlTemplate};
+    use crate::{CompileOptions, Namespace};
+
+    const NONE: [(&str, &str); 0] = [];
+
+    #[test]
+    fn render_tests() {
+        assert_eq!(render("<div></div>", NONE), Ok("<div></div>".to_owned()));
+        assert_eq!(
+            render("<textarea>#value</textarea>", vec![("value", "<b>")]),
+            Ok("<textarea>&lt;b&gt;</textarea>".to_owned())
+        );
+        assert!(render("<title>Hello #name</title>", vec![("name", "Ada")]).is_err());
+        assert_eq!(
+            render("<div #root><h1>#count</h1></div>", vec![("count", "3")]),
+            Ok("<div><h1>3</h1></div>".to_owned())
+        );
+        assert_eq!(
+            render(
+                "<p class='#cls' title='a &amp; b'>Hello #name!</p>",
+                vec![("name", "<Ada & co>"), ("cls", "x\"y")]
+            ),
+            Ok(
+                "<p class=\"x&quot;y\" title=\"a &amp; b\">Hello <!---->&lt;Ada &amp; co&gt;<!---->!</p>"
+                    .to_owned()
+            )
+        );
+        assert_eq!(
+            render("<input #box value='#value'><br>", vec![("value", "hi")]),
+            Ok("<input value=\"hi\"><br>".to_owned())
+        );
+        assert_eq!(
+            render("#a and #b", vec![("b", "2")]),
+            Ok("<!----> and <!---->2".to_owned())
+        );
+        assert_eq!(
+            render(
+                "<svg viewBox='0 0 1 1'><circle r='#r'/></svg>",
+                vec![("r", "1")]
+            ),
+            Ok("<svg viewBox=\"0 0 1 1\"><circle r=\"1\"></circle></svg>".to_owned())
+        );
+        assert_eq!(
+            render("<style>a > b { color: #fff }</style>", NONE),
+            Ok("<style>a > b { color: #fff }</style>".to_owned())
+        );
+    }
+
+    #[test]
+    fn render_anchor_tests() {
+        let item = HtmlTemplate::parse("<li>#title</li>").unwrap();
+        let items = ["a", "<b>"]
+            .iter()
+            .map(|title| item.render(vec![("title", title)]))
+            .collect::<Result<String, _>>()
+            .unwrap();
+        assert_eq!(
+            render("<ul><!--#items--></ul>", vec![("items", items.as_str())]),
+            Ok("<ul><!--items--><li>a</li><li>&lt;b&gt;</li><!--/items--></ul>".to_owned())
+        );
+
+        let list = HtmlTemplate::parse("<ul #list><!--#items--><li #last></li></ul>").unwrap();
+        assert_eq!(
+            list.ref_names().collect::<Vec<_>>(),
+            vec!["list", "items", "last"]
+        );
+        assert_eq!(
+            list.render(NONE),
+            Ok("<ul><!--items--><!--/items--><li></li></ul>".to_owned())
+        );
+    }
+
+    #[test]
+    fn render_options_tests() {
+        let options = CompileOptions {
+            sigil: '@',
+            ..CompileOptions::default()
+        };
+        let template =
+            HtmlTemplate::parse_with("<p @el title='#x'>@@x @name</p>", Namespace::Html, &options)
+                .unwrap();
+        assert_eq!(template.ref_names().collect::<Vec<_>>(), vec!["el", "name"]);
+        assert_eq!(
+            template.render(vec![("name", "Ada")]),
+            Ok("<p title=\"#x\">@x <!---->Ada</p>".to_owned())
+        );
+
+        let options = CompileOptions {
+            sigil: ' ',
+            ..CompileOptions::default()
+        };
+        assert!(HtmlTemplate::parse_with("<p></p>", Namespace::Html, &options).is_err());
+    }
+
+    #[test]
+    fn render_error_tests() {
+        assert!(render("<div></div>", vec![("missing", "")]).is_err());
+        assert!(render("<div #el></div>", vec![("el", "x")]).is_err());
+        assert!(render("<script>#x</script>", vec![("x", "1")]).is_err());
+        assert!(render("<div #a #b></div>", NONE).is_err());
+    }
+}
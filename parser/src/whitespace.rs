@@ -0,0 +1,198 @@
+use super::Node;
+
+/// Elements that start a new line, so whitespace next to them isn't rendered.
+pub const BLOCK_ELEMENTS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "body",
+    "dd",
+    "details",
+    "dialog",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hgroup",
+    "hr",
+    "html",
+    "li",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "tr",
+    "ul",
+];
+
+/// Elements whose whitespace is kept as written.
+pub const PREFORMATTED_ELEMENTS: &[&str] = &["pre", "textarea", "script", "style"];
+
+/// Collapses each run of ASCII whitespace in `value` to a single space, dropping it at
+/// the start when the text follows a block boundary and at the end when it precedes one.
+/// Other whitespace such as `&nbsp;` is significant and left alone.
+pub fn collapse_text(value: &str, after_block: bool, before_block: bool) -> String {
+    let mut collapsed = String::with_capacity(value.len());
+    let mut space = false;
+    for c in value.chars() {
+        if !c.is_ascii_whitespace() {
+            collapsed.push(c);
+            space = false;
+        } else if !space {
+            collapsed.push(' ');
+            space = true;
+        }
+    }
+    if after_block {
+        collapsed = collapsed
+            .trim_start_matches(|c: char| c.is_ascii_whitespace())
+            .to_owned();
+    }
+    if before_block {
+        let len = collapsed
+            .trim_end_matches(|c: char| c.is_ascii_whitespace())
+            .len();
+        collapsed.truncate(len);
+    }
+    collapsed
+}
+
+/// Whether a text node's `sibling` is a block boundary for [`collapse_text`]. A missing
+/// sibling means the edge of the parent, which counts as a boundary when the parent itself
+/// is a block. `element_name` returns the sibling's tag name, or `None` if it isn't an
+/// element.
+pub fn is_block<T, S: AsRef<str>>(
+    sibling: Option<T>,
+    parent_block: bool,
+    element_name: impl FnOnce(T) -> Option<S>,
+) -> bool {
+    match sibling {
+        None => parent_block,
+        Some(sibling) => {
+            element_name(sibling).is_some_and(|name| BLOCK_ELEMENTS.contains(&name.as_ref()))
+        }
+    }
+}
+
+fn element_name(node: &Node) -> Option<&str> {
+    match node {
+        Node::Element(el) => Some(el.name.as_str()),
+        _ => None,
+    }
+}
+
+fn collapse_children(children: &mut Vec<Node>, block: bool) {
+    let mut collapsed: Vec<Node> = Vec::with_capacity(children.len());
+    let mut rest = std::mem::take(children).into_iter().peekable();
+    while let Some(mut node) = rest.next() {
+        match &mut node {
+            Node::Element(el) => {
+                if !PREFORMATTED_ELEMENTS.contains(&el.name.as_str()) {
+                    let block = BLOCK_ELEMENTS.contains(&el.name.as_str());
+                    collapse_children(&mut el.children, block);
+                }
+            }
+            Node::Text(value) => {
+                *value = collapse_text(
+                    value,
+                    is_block(collapsed.last(), block, element_name),
+                    is_block(rest.peek(), block, element_name),
+                );
+                if value.is_empty() {
+                    continue;
+                }
+            }
+            Node::Comment(_) => {}
+        }
+        collapsed.push(node);
+    }
+    *children = collapsed;
+}
+
+/// Collapses whitespace in parsed `roots` exactly like the runtime `compile_with` does
+/// with `collapse_whitespace` set, so server-rendered HTML still hydrates.
+pub fn collapse_whitespace(roots: &mut Vec<Node>) {
+    match roots.as_mut_slice() {
+        [Node::Element(el)] => {
+            if !PREFORMATTED_ELEMENTS.contains(&el.name.as_str()) {
+                let block = BLOCK_ELEMENTS.contains(&el.name.as_str());
+                collapse_children(&mut el.children, block);
+            }
+        }
+        _ => collapse_children(roots, true),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{collapse_text, collapse_whitespace};
+    use crate::{parse, Namespace, Node};
+
+    fn collapse(source: &str) -> Vec<Node> {
+        let mut roots = parse(source, Namespace::Html).unwrap();
+        collapse_whitespace(&mut roots);
+        roots
+    }
+
+    #[test]
+    fn collapse_text_tests() {
+        assert_eq!(collapse_text(" a \n\t b ", false, false), " a b ");
+        assert_eq!(collapse_text(" a  b ", true, true), "a b");
+        assert_eq!(
+            collapse_text("\u{a0} a \u{a0}", true, true),
+            "\u{a0} a \u{a0}"
+        );
+        assert_eq!(collapse_text(" \u{a0}  ", true, true), "\u{a0}");
+        assert_eq!(collapse_text(" \n ", true, false), "");
+    }
+
+    #[test]
+    fn collapse_whitespace_tests() {
+        assert_eq!(
+            collapse("<div>\n  <h1>x</h1>\n  <p>  a <b>b</b>\n <i>c</i>  </p>\n</div>"),
+            parse(
+                "<div><h1>x</h1><p>a <b>b</b> <i>c</i></p></div>",
+                Namespace::Html
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            collapse("<p>&nbsp;a&nbsp;</p>"),
+            parse("<p>&nbsp;a&nbsp;</p>", Namespace::Html).unwrap()
+        );
+        assert_eq!(
+            collapse("<pre> a\n  b </pre>"),
+            parse("<pre> a\n  b </pre>", Namespace::Html).unwrap()
+        );
+        assert_eq!(
+            collapse("<span> a </span>  <p> b </p>"),
+            parse("<span> a </span><p>b</p>", Namespace::Html).unwrap()
+        );
+        assert_eq!(
+            collapse("a  <b>b</b>"),
+            parse("a <b>b</b>", Namespace::Html).unwrap()
+        );
+    }
+}
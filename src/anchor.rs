@@ -0,0 +1,71 @@
+use super::dom::{Dom, NodeKind};
+use super::Stage0Error;
+use web_sys::Node;
+
+/// A region delimited by a `<!--#name-->` comment ref and the end comment that `compile`
+/// inserts right after it. Content mounted into the region sits between the two, so it
+/// can share a parent with static siblings.
+#[derive(Clone, Debug)]
+pub struct Anchor<N = Node> {
+    start: N,
+    end: N,
+}
+
+impl<N: Dom> Anchor<N> {
+    /// Wraps a collected comment ref, finding its end comment among the following
+    /// siblings so that regions already filled in, e.g. by hydration, work too.
+    pub fn new(start: N) -> Result<Anchor<N>, Stage0Error> {
+        let name = start.node_value().unwrap_or_default();
+        if start.kind() == NodeKind::Comment {
+            let end_value = format!("/{}", name);
+            let mut node = start.next_sibling();
+            while let Some(end) = node {
+                if end.kind() == NodeKind::Comment
+                    && end.node_value().as_deref() == Some(end_value.as_str())
+                {
+                    return Ok(Anchor { start, end });
+                }
+                node = end.next_sibling();
+            }
+        }
+        Err(Stage0Error::WrongNodeType {
+            name,
+            expected: "comment anchor",
+        })
+    }
+
+    pub fn parent(&self) -> Option<N> {
+        self.start.parent_node()
+    }
+
+    /// The `before_node` to pass to `reconcile` for lists rendered in this region.
+    pub fn before_node(&self) -> Option<N> {
+        Some(self.start.clone())
+    }
+
+    /// The `after_node` to pass to `reconcile` for lists rendered in this region.
+    pub fn after_node(&self) -> Option<N> {
+        Some(self.end.clone())
+    }
+
+    /// Inserts `node` at the end of the region.
+    pub fn mount(&self, node: &N) -> Result<(), Stage0Error> {
+        if let Some(parent) = self.parent() {
+            parent.insert_before(node, Some(&self.end))?;
+        }
+        Ok(())
+    }
+
+    /// Removes everything between the two comments.
+    pub fn clear(&self) -> Result<(), Stage0Error> {
+        if let Some(parent) = self.parent() {
+            while let Some(node) = self.start.next_sibling() {
+                if node.is_same_node(&self.end) {
+                    break;
+                }
+                parent.remove_child(&node)?;
+            }
+        }
+        Ok(())
+    }
+}
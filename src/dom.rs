@@ -0,0 +1,196 @@
+//! The DOM operations stage0 is built on. `web_sys::Node` implements them against the
+//! browser, and `MemoryNode` against an in-memory tree so templates and reconciliation
+//! run under a native `cargo test`.
+
+mod memory;
+
+pub use memory::MemoryNode;
+
+use super::{Stage0Error, DOCUMENT};
+use std::fmt;
+use wasm_bindgen::JsCast;
+use web_sys::{Document, Element, Node};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeKind {
+    Element,
+    Text,
+    Comment,
+    Attribute,
+    Fragment,
+    Other,
+}
+
+/// A handle to a DOM node. Clones refer to the same node.
+pub trait Dom: Clone + fmt::Debug {
+    fn kind(&self) -> NodeKind;
+
+    /// The local name of an element or attribute, like `div`; `#text` and friends for
+    /// other nodes.
+    fn node_name(&self) -> String;
+
+    fn node_value(&self) -> Option<String>;
+
+    fn set_node_value(&self, value: Option<&str>);
+
+    fn parent_node(&self) -> Option<Self>;
+
+    fn first_child(&self) -> Option<Self>;
+
+    fn last_child(&self) -> Option<Self>;
+
+    fn next_sibling(&self) -> Option<Self>;
+
+    fn previous_sibling(&self) -> Option<Self>;
+
+    fn is_same_node(&self, other: &Self) -> bool;
+
+    /// Inserts `node` before `before`, or at the end when it's `None`. A fragment's
+    /// children are moved in its place.
+    fn insert_before(&self, node: &Self, before: Option<&Self>) -> Result<(), Stage0Error>;
+
+    fn append_child(&self, node: &Self) -> Result<(), Stage0Error> {
+        self.insert_before(node, None)
+    }
+
+    fn remove_child(&self, node: &Self) -> Result<(), Stage0Error>;
+
+    fn attribute_names(&self) -> Vec<String>;
+
+    fn get_attribute(&self, name: &str) -> Option<String>;
+
+    fn set_attribute(&self, name: &str, value: &str) -> Result<(), Stage0Error>;
+
+    fn remove_attribute(&self, name: &str) -> Result<(), Stage0Error>;
+
+    /// The attribute node for `name`, whose value stays live like any text ref.
+    fn attribute_node(&self, name: &str) -> Option<Self>;
+
+    fn clone_deep(&self) -> Result<Self, Stage0Error>;
+
+    /// Creates a text node in the same document as `self`.
+    fn create_text(&self, value: &str) -> Result<Self, Stage0Error>;
+
+    /// Creates a comment in the same document as `self`.
+    fn create_comment(&self, value: &str) -> Result<Self, Stage0Error>;
+}
+
+impl Dom for Node {
+    fn kind(&self) -> NodeKind {
+        match self.node_type() {
+            Node::ELEMENT_NODE => NodeKind::Element,
+            Node::TEXT_NODE => NodeKind::Text,
+            Node::COMMENT_NODE => NodeKind::Comment,
+            Node::ATTRIBUTE_NODE => NodeKind::Attribute,
+            Node::DOCUMENT_FRAGMENT_NODE => NodeKind::Fragment,
+            _ => NodeKind::Other,
+        }
+    }
+
+    fn node_name(&self) -> String {
+        match self.dyn_ref::<Element>() {
+            Some(el) => el.local_name(),
+            None => Node::node_name(self),
+        }
+    }
+
+    fn node_value(&self) -> Option<String> {
+        Node::node_value(self)
+    }
+
+    fn set_node_value(&self, value: Option<&str>) {
+        Node::set_node_value(self, value)
+    }
+
+    fn parent_node(&self) -> Option<Node> {
+        Node::parent_node(self)
+    }
+
+    fn first_child(&self) -> Option<Node> {
+        Node::first_child(self)
+    }
+
+    fn last_child(&self) -> Option<Node> {
+        Node::last_child(self)
+    }
+
+    fn next_sibling(&self) -> Option<Node> {
+        Node::next_sibling(self)
+    }
+
+    fn previous_sibling(&self) -> Option<Node> {
+        Node::previous_sibling(self)
+    }
+
+    fn is_same_node(&self, other: &Node) -> bool {
+        Node::is_same_node(self, Some(other))
+    }
+
+    fn insert_before(&self, node: &Node, before: Option<&Node>) -> Result<(), Stage0Error> {
+        Node::insert_before(self, node, before)?;
+        Ok(())
+    }
+
+    fn remove_child(&self, node: &Node) -> Result<(), Stage0Error> {
+        Node::remove_child(self, node)?;
+        Ok(())
+    }
+
+    fn attribute_names(&self) -> Vec<String> {
+        match self.dyn_ref::<Element>() {
+            Some(el) => {
+                let attrs = el.attributes();
+                (0..attrs.length())
+                    .filter_map(|i| attrs.item(i))
+                    .map(|attr| attr.name())
+                    .collect()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    fn get_attribute(&self, name: &str) -> Option<String> {
+        self.dyn_ref::<Element>()?.get_attribute(name)
+    }
+
+    fn set_attribute(&self, name: &str, value: &str) -> Result<(), Stage0Error> {
+        if let Some(el) = self.dyn_ref::<Element>() {
+            el.set_attribute(name, value)?;
+        }
+        Ok(())
+    }
+
+    fn remove_attribute(&self, name: &str) -> Result<(), Stage0Error> {
+        if let Some(el) = self.dyn_ref::<Element>() {
+            el.remove_attribute(name)?;
+        }
+        Ok(())
+    }
+
+    fn attribute_node(&self, name: &str) -> Option<Node> {
+        self.dyn_ref::<Element>()?
+            .get_attribute_node(name)
+            .map(Into::into)
+    }
+
+    fn clone_deep(&self) -> Result<Node, Stage0Error> {
+        Ok(self.clone_node_with_deep(true)?)
+    }
+
+    fn create_text(&self, value: &str) -> Result<Node, Stage0Error> {
+        Ok(owner_document(self).create_text_node(value).into())
+    }
+
+    fn create_comment(&self, value: &str) -> Result<Node, Stage0Error> {
+        Ok(owner_document(self).create_comment(value).into())
+    }
+}
+
+// A document has no owner document of its own, and a node that somehow has neither falls
+// back to the global one.
+fn owner_document(node: &Node) -> Document {
+    node.dyn_ref::<Document>()
+        .cloned()
+        .or_else(|| node.owner_document())
+        .unwrap_or_else(|| DOCUMENT.0.clone())
+}
@@ -0,0 +1,547 @@
+use super::{Dom, NodeKind};
+use crate::Stage0Error;
+use stage0_parser::Namespace;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+struct Data {
+    kind: NodeKind,
+    name: String,
+    value: Option<String>,
+    attrs: Vec<MemoryNode>,
+    parent: Weak<RefCell<Data>>,
+    children: Vec<MemoryNode>,
+}
+
+/// A node of an in-memory DOM, for running templates and reconciliation without a
+/// browser. Clones are handles to the same node, and equality is identity.
+#[derive(Clone)]
+pub struct MemoryNode(Rc<RefCell<Data>>);
+
+impl MemoryNode {
+    fn new(kind: NodeKind, name: &str, value: Option<&str>) -> MemoryNode {
+        MemoryNode(Rc::new(RefCell::new(Data {
+            kind,
+            name: name.to_owned(),
+            value: value.map(ToOwned::to_owned),
+            attrs: Vec::new(),
+            parent: Weak::new(),
+            children: Vec::new(),
+        })))
+    }
+
+    pub fn element(name: &str) -> MemoryNode {
+        MemoryNode::new(NodeKind::Element, name, None)
+    }
+
+    pub fn text(value: &str) -> MemoryNode {
+        MemoryNode::new(NodeKind::Text, "#text", Some(value))
+    }
+
+    pub fn comment(value: &str) -> MemoryNode {
+        MemoryNode::new(NodeKind::Comment, "#comment", Some(value))
+    }
+
+    pub fn fragment() -> MemoryNode {
+        MemoryNode::new(NodeKind::Fragment, "#document-fragment", None)
+    }
+
+    /// Parses `source` like `stage0::h` does, into a single root or, for several roots or
+    /// a lone text node, a fragment. Ready to be passed to `compile`.
+    pub fn parse(source: &str) -> Result<MemoryNode, Stage0Error> {
+        let roots = stage0_parser::parse(source.trim(), Namespace::Html)?;
+        match roots.as_slice() {
+            [] => Err(Stage0Error::EmptyTemplate),
+            [root] if !matches!(root, stage0_parser::Node::Text(_)) => Ok(MemoryNode::build(root)),
+            roots => {
+                let fragment = MemoryNode::fragment();
+                for root in roots.iter() {
+                    fragment.append_child(&MemoryNode::build(root))?;
+                }
+                Ok(fragment)
+            }
+        }
+    }
+
+    fn build(node: &stage0_parser::Node) -> MemoryNode {
+        match node {
+            stage0_parser::Node::Element(el) => {
+                let node = MemoryNode::element(&el.name);
+                for (name, value) in el.attrs.iter() {
+                    node.set_attr(name, value);
+                }
+                for child in el.children.iter() {
+                    node.push_child(MemoryNode::build(child));
+                }
+                node
+            }
+            stage0_parser::Node::Text(value) => MemoryNode::text(value),
+            stage0_parser::Node::Comment(value) => MemoryNode::comment(value),
+        }
+    }
+
+    fn parent(&self) -> Option<MemoryNode> {
+        self.0.borrow().parent.upgrade().map(MemoryNode)
+    }
+
+    fn index(&self) -> Option<(MemoryNode, usize)> {
+        let parent = self.parent()?;
+        let idx = parent
+            .0
+            .borrow()
+            .children
+            .iter()
+            .position(|child| child == self)?;
+        Some((parent, idx))
+    }
+
+    fn sibling(&self, offset: isize) -> Option<MemoryNode> {
+        let (parent, idx) = self.index()?;
+        let idx = idx as isize + offset;
+        if idx < 0 {
+            return None;
+        }
+        let sibling = parent.0.borrow().children.get(idx as usize).cloned();
+        sibling
+    }
+
+    fn push_child(&self, child: MemoryNode) {
+        child.0.borrow_mut().parent = Rc::downgrade(&self.0);
+        self.0.borrow_mut().children.push(child);
+    }
+
+    fn detach(&self) {
+        if let Some((parent, idx)) = self.index() {
+            parent.0.borrow_mut().children.remove(idx);
+        }
+        self.0.borrow_mut().parent = Weak::new();
+    }
+
+    fn set_attr(&self, name: &str, value: &str) {
+        match self.attribute_node(name) {
+            Some(attr) => attr.0.borrow_mut().value = Some(value.to_owned()),
+            None => {
+                let attr = MemoryNode::new(NodeKind::Attribute, name, Some(value));
+                self.0.borrow_mut().attrs.push(attr);
+            }
+        }
+    }
+
+    fn contains(&self, other: &MemoryNode) -> bool {
+        let mut node = Some(other.clone());
+        while let Some(current) = node {
+            if current == *self {
+                return true;
+            }
+            node = current.parent();
+        }
+        false
+    }
+
+    /// Serializes the node and its descendants, without escaping.
+    pub fn to_html(&self) -> String {
+        let mut html = String::new();
+        self.write_html(&mut html);
+        html
+    }
+
+    fn write_html(&self, html: &mut String) {
+        let data = self.0.borrow();
+        match data.kind {
+            NodeKind::Element => {
+                html.push('<');
+                html.push_str(&data.name);
+                for attr in data.attrs.iter() {
+                    let attr = attr.0.borrow();
+                    html.push_str(&format!(
+                        " {}=\"{}\"",
+                        attr.name,
+                        attr.value.as_deref().unwrap_or_default()
+                    ));
+                }
+                html.push('>');
+                for child in data.children.iter() {
+                    child.write_html(html);
+                }
+                html.push_str(&format!("</{}>", data.name));
+            }
+            NodeKind::Comment => html.push_str(&format!(
+                "<!--{}-->",
+                data.value.as_deref().unwrap_or_default()
+            )),
+            NodeKind::Fragment => {
+                for child in data.children.iter() {
+                    child.write_html(html);
+                }
+            }
+            _ => html.push_str(data.value.as_deref().unwrap_or_default()),
+        }
+    }
+}
+
+impl PartialEq for MemoryNode {
+    fn eq(&self, other: &MemoryNode) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for MemoryNode {}
+
+impl fmt::Debug for MemoryNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_html())
+    }
+}
+
+impl Dom for MemoryNode {
+    fn kind(&self) -> NodeKind {
+        self.0.borrow().kind
+    }
+
+    fn node_name(&self) -> String {
+        self.0.borrow().name.clone()
+    }
+
+    fn node_value(&self) -> Option<String> {
+        self.0.borrow().value.clone()
+    }
+
+    fn set_node_value(&self, value: Option<&str>) {
+        let mut data = self.0.borrow_mut();
+        if data.value.is_some() {
+            data.value = Some(value.unwrap_or_default().to_owned());
+        }
+    }
+
+    fn parent_node(&self) -> Option<MemoryNode> {
+        self.parent()
+    }
+
+    fn first_child(&self) -> Option<MemoryNode> {
+        self.0.borrow().children.first().cloned()
+    }
+
+    fn last_child(&self) -> Option<MemoryNode> {
+        self.0.borrow().children.last().cloned()
+    }
+
+    fn next_sibling(&self) -> Option<MemoryNode> {
+        self.sibling(1)
+    }
+
+    fn previous_sibling(&self) -> Option<MemoryNode> {
+        self.sibling(-1)
+    }
+
+    fn is_same_node(&self, other: &MemoryNode) -> bool {
+        self == other
+    }
+
+    fn insert_before(
+        &self,
+        node: &MemoryNode,
+        before: Option<&MemoryNode>,
+    ) -> Result<(), Stage0Error> {
+        if let Some(before) = before {
+            if before.parent().as_ref() != Some(self) {
+                return Err(Stage0Error::Hierarchy(
+                    "the reference node isn't a child of this node".to_owned(),
+                ));
+            }
+            if before == node {
+                return Ok(());
+            }
+        }
+        match self.kind() {
+            NodeKind::Element | NodeKind::Fragment => {}
+            _ => {
+                return Err(Stage0Error::Hierarchy(format!(
+                    "`{}` can't have children",
+                    self.node_name()
+                )))
+            }
+        }
+        if node.contains(self) {
+            return Err(Stage0Error::Hierarchy(
+                "a node can't be inserted into itself".to_owned(),
+            ));
+        }
+
+        let nodes = if node.kind() == NodeKind::Fragment {
+            std::mem::take(&mut node.0.borrow_mut().children)
+        } else {
+            node.detach();
+            vec![node.clone()]
+        };
+        let idx = match before {
+            Some(before) => before.index().map_or(0, |(_, idx)| idx),
+            None => self.0.borrow().children.len(),
+        };
+        for (offset, child) in nodes.into_iter().enumerate() {
+            child.0.borrow_mut().parent = Rc::downgrade(&self.0);
+            self.0.borrow_mut().children.insert(idx + offset, child);
+        }
+        Ok(())
+    }
+
+    fn remove_child(&self, node: &MemoryNode) -> Result<(), Stage0Error> {
+        if node.parent().as_ref() != Some(self) {
+            return Err(Stage0Error::Hierarchy(
+                "the node to remove isn't a child of this node".to_owned(),
+            ));
+        }
+        node.detach();
+        Ok(())
+    }
+
+    fn attribute_names(&self) -> Vec<String> {
+        self.0
+            .borrow()
+            .attrs
+            .iter()
+            .map(|attr| attr.node_name())
+            .collect()
+    }
+
+    fn get_attribute(&self, name: &str) -> Option<String> {
+        self.attribute_node(name)?.node_value()
+    }
+
+    fn set_attribute(&self, name: &str, value: &str) -> Result<(), Stage0Error> {
+        if self.kind() == NodeKind::Element {
+            self.set_attr(name, value);
+        }
+        Ok(())
+    }
+
+    fn remove_attribute(&self, name: &str) -> Result<(), Stage0Error> {
+        self.0
+            .borrow_mut()
+            .attrs
+            .retain(|attr| attr.node_name() != name);
+        Ok(())
+    }
+
+    fn attribute_node(&self, name: &str) -> Option<MemoryNode> {
+        self.0
+            .borrow()
+            .attrs
+            .iter()
+            .find(|attr| attr.node_name() == name)
+            .cloned()
+    }
+
+    fn clone_deep(&self) -> Result<MemoryNode, Stage0Error> {
+        let data = self.0.borrow();
+        let node = MemoryNode::new(data.kind, &data.name, data.value.as_deref());
+        for attr in data.attrs.iter() {
+            node.0.borrow_mut().attrs.push(attr.clone_deep()?);
+        }
+        for child in data.children.iter() {
+            node.push_child(child.clone_deep()?);
+        }
+        Ok(node)
+    }
+
+    fn create_text(&self, value: &str) -> Result<MemoryNode, Stage0Error> {
+        Ok(MemoryNode::text(value))
+    }
+
+    fn create_comment(&self, value: &str) -> Result<MemoryNode, Stage0Error> {
+        Ok(MemoryNode::comment(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryNode;
+    use crate::dom::Dom;
+    use crate::{compile, compile_with, CompileOptions, Namespace, Stage0Error};
+
+    #[test]
+    fn memory_dom_tests() {
+        let list = MemoryNode::element("ul");
+        let a = MemoryNode::element("a");
+        let b = MemoryNode::element("b");
+        list.append_child(&a).unwrap();
+        list.insert_before(&b, Some(&a)).unwrap();
+        assert_eq!(list.to_html(), "<ul><b></b><a></a></ul>");
+        assert_eq!(a.previous_sibling(), Some(b.clone()));
+        assert_eq!(b.parent_node(), Some(list.clone()));
+
+        list.append_child(&b).unwrap();
+        assert_eq!(list.to_html(), "<ul><a></a><b></b></ul>");
+        list.remove_child(&a).unwrap();
+        assert_eq!(a.parent_node(), None);
+        assert!(list.remove_child(&a).is_err());
+        assert!(b.append_child(&list).is_err());
+
+        let fragment = MemoryNode::fragment();
+        fragment.append_child(&MemoryNode::text("x")).unwrap();
+        fragment.append_child(&MemoryNode::comment("y")).unwrap();
+        list.insert_before(&fragment, Some(&b)).unwrap();
+        assert_eq!(list.to_html(), "<ul>x<!--y--><b></b></ul>");
+        assert_eq!(fragment.first_child(), None);
+
+        b.set_attribute("class", "on").unwrap();
+        let class = b.attribute_node("class").unwrap();
+        class.set_node_value(Some("off"));
+        assert_eq!(b.get_attribute("class"), Some("off".to_owned()));
+
+        let copy = list.clone_deep().unwrap();
+        assert_eq!(copy.to_html(), list.to_html());
+        assert_ne!(copy, list);
+    }
+
+    #[test]
+    fn memory_template_tests() {
+        let node = MemoryNode::parse("<p #el class='#cls'>Hello #name! <!--#items--></p>").unwrap();
+        let template = compile(node).unwrap();
+        let mut instance = template.instantiate().unwrap();
+        let refs = instance.collect();
+        assert_eq!(refs.len(), 4);
+
+        refs.get("name").unwrap().set_node_value(Some("Ada"));
+        refs.get("cls").unwrap().set_node_value(Some("big"));
+        let items = refs.get("items").unwrap();
+        let parent = items.parent_node().unwrap();
+        parent
+            .insert_before(&MemoryNode::text("1"), items.next_sibling().as_ref())
+            .unwrap();
+        assert_eq!(
+            instance.node().to_html(),
+            "<p class=\"big\">Hello Ada! <!--items-->1<!--/items--></p>"
+        );
+        assert_eq!(
+            template.node().to_html(),
+            "<p class=\"\">Hello ! <!--items--><!--/items--></p>"
+        );
+
+        let indexed = template.collect_indexed().unwrap();
+        assert_eq!(indexed[template.ref_index("el").unwrap()], *template.node());
+
+        let node = MemoryNode::parse("<style>.a{color:#fff}</style>").unwrap();
+        let template = compile(node).unwrap();
+        assert!(template.collect().unwrap().is_empty());
+        assert_eq!(template.node().to_html(), "<style>.a{color:#fff}</style>");
+    }
+
+    #[test]
+    fn memory_compile_options_tests() {
+        let options = CompileOptions {
+            sigil: '@',
+            collapse_whitespace: true,
+        };
+        let node = MemoryNode::parse(
+            "<div>
+                <h1 title='@@x'>@count</h1>
+                <p>  ##1 <b>a</b>
+                    <i>b</i> </p>
+            </div>",
+        )
+        .unwrap();
+        let template = compile_with(node, &options).unwrap();
+        assert_eq!(
+            template.node().to_html(),
+            "<div><h1 title=\"@x\"></h1><p>##1 <b>a</b> <i>b</i></p></div>"
+        );
+
+        let source = "<p> \u{a0}#x\u{a0} <b>a</b> </p>";
+        let options = CompileOptions {
+            collapse_whitespace: true,
+            ..CompileOptions::default()
+        };
+        let template = compile_with(MemoryNode::parse(source).unwrap(), &options).unwrap();
+        assert_eq!(template.node().to_html(), "<p>\u{a0}\u{a0} <b>a</b></p>");
+        let html = stage0_parser::HtmlTemplate::parse_with(source, Namespace::Html, &options)
+            .unwrap()
+            .render(vec![("x", "1")])
+            .unwrap();
+        let live = MemoryNode::parse(&html).unwrap();
+        let refs = template.hydrate(&live).unwrap();
+        assert_eq!(refs.get("x").unwrap().node_value(), Some("1".to_owned()));
+
+        let node = MemoryNode::parse("<div #a><p #a></p></div>").unwrap();
+        assert_eq!(
+            compile(node).err(),
+            Some(Stage0Error::DuplicateRef("a".to_owned()))
+        );
+
+        for source in [
+            "<div #a #b></div>",
+            "<div ##a></div>",
+            "<div #a.b></div>",
+            "<textarea>Hello #name</textarea>",
+        ]
+        .iter()
+        {
+            let node = MemoryNode::parse(source).unwrap();
+            assert!(
+                matches!(compile(node), Err(Stage0Error::Parse(_))),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn memory_hydrate_tests() {
+        let source = "<ul><li>#a and #b</li><!--#rest--></ul>";
+        let template = compile(MemoryNode::parse(source).unwrap()).unwrap();
+        let html = stage0_parser::render(source, vec![("b", "2"), ("rest", "<li>x</li>")]).unwrap();
+        let live = MemoryNode::parse(&html).unwrap();
+
+        let refs = template.hydrate(&live).unwrap();
+        refs.get("a").unwrap().set_node_value(Some("1"));
+        assert_eq!(
+            live.to_html(),
+            "<ul><li>1 and 2</li><!--rest--><li>x</li><!--/rest--></ul>"
+        );
+
+        let live = MemoryNode::parse("<ul><li><!----> and </li><p></p></ul>").unwrap();
+        assert!(matches!(
+            template.hydrate(&live),
+            Err(Stage0Error::HydrationMismatch { path, .. }) if path == vec![1]
+        ));
+
+        // The first item needs a text node and loses its separators, but nothing changes
+        // when a later node doesn't match.
+        let html = html.replace("<!--rest-->", "<p></p>");
+        let live = MemoryNode::parse(&html).unwrap();
+        assert!(template.hydrate(&live).is_err());
+        assert_eq!(live.to_html(), html);
+
+        // Static text has to match, and text merged without separators isn't split.
+        let template = compile(MemoryNode::parse("<p>Hello #name!</p>").unwrap()).unwrap();
+        for html in ["<p>Hello Ada!</p>", "<p>Totally different text</p>"].iter() {
+            let live = MemoryNode::parse(html).unwrap();
+            assert_eq!(
+                template.hydrate(&live).err(),
+                Some(Stage0Error::HydrationMismatch {
+                    path: vec![0],
+                    expected: "text \"Hello \"".to_owned(),
+                    found: format!("text {:?}", &html[3..html.len() - 4]),
+                })
+            );
+        }
+
+        let options = CompileOptions {
+            sigil: '@',
+            ..CompileOptions::default()
+        };
+        let source = "<p @el>##1 @name</p>";
+        let template = compile_with(MemoryNode::parse(source).unwrap(), &options).unwrap();
+        let html = stage0_parser::HtmlTemplate::parse_with(source, Namespace::Html, &options)
+            .unwrap()
+            .render(vec![("name", "Ada")])
+            .unwrap();
+        let live = MemoryNode::parse(&html).unwrap();
+        let refs = template.hydrate(&live).unwrap();
+        assert_eq!(refs.get("el"), Some(&live));
+        assert_eq!(
+            refs.get("name").unwrap().node_value(),
+            Some("Ada".to_owned())
+        );
+    }
+}
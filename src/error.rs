@@ -0,0 +1,86 @@
+use std::fmt;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stage0Error {
+    Parse(String),
+    EmptyTemplate,
+    DuplicateRef(String),
+    MissingRef(String),
+    WrongNodeType {
+        name: String,
+        expected: &'static str,
+    },
+    HydrationMismatch {
+        path: Vec<u32>,
+        expected: String,
+        found: String,
+    },
+    DuplicateKey(usize),
+    EmptyFragment,
+    RenderedCount {
+        expected: usize,
+        found: usize,
+    },
+    Hierarchy(String),
+    Dom(JsValue),
+}
+
+impl fmt::Display for Stage0Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stage0Error::Parse(message) => write!(f, "failed to parse template: {}", message),
+            Stage0Error::EmptyTemplate => f.write_str("template is empty"),
+            Stage0Error::DuplicateRef(name) => write!(f, "duplicate ref `#{}`", name),
+            Stage0Error::MissingRef(name) => write!(f, "missing ref `#{}`", name),
+            Stage0Error::WrongNodeType { name, expected } => {
+                write!(f, "ref `#{}` is not a `{}`", name, expected)
+            }
+            Stage0Error::HydrationMismatch {
+                path,
+                expected,
+                found,
+            } => write!(
+                f,
+                "hydration mismatch at {:?}: expected {}, found {}",
+                path, expected, found
+            ),
+            Stage0Error::DuplicateKey(index) => {
+                write!(f, "item {} has the same key as an earlier one", index)
+            }
+            Stage0Error::EmptyFragment => f.write_str("an item rendered an empty fragment"),
+            Stage0Error::RenderedCount { expected, found } => {
+                write!(f, "expected {} rendered nodes, found {}", expected, found)
+            }
+            Stage0Error::Hierarchy(message) => write!(f, "invalid DOM operation: {}", message),
+            Stage0Error::Dom(value) => match value.dyn_ref::<js_sys::Error>() {
+                Some(err) => write!(f, "DOM exception: {}", String::from(err.message())),
+                None => write!(f, "DOM exception: {:?}", value),
+            },
+        }
+    }
+}
+
+impl std::error::Error for Stage0Error {}
+
+impl From<JsValue> for Stage0Error {
+    fn from(value: JsValue) -> Stage0Error {
+        Stage0Error::Dom(value)
+    }
+}
+
+impl From<stage0_parser::Error> for Stage0Error {
+    fn from(err: stage0_parser::Error) -> Stage0Error {
+        Stage0Error::Parse(err.to_string())
+    }
+}
+
+impl From<Stage0Error> for JsValue {
+    fn from(err: Stage0Error) -> JsValue {
+        match err {
+            Stage0Error::Dom(value) => value,
+            err => js_sys::Error::new(&err.to_string()).into(),
+        }
+    }
+}
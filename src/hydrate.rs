@@ -0,0 +1,181 @@
+use super::dom::{Dom, NodeKind};
+use super::{Ref, Stage0Error};
+use std::collections::HashMap;
+
+// Describes a node for mismatch errors, e.g. `<li>`, `text "Hello "` or `<!--items-->`.
+fn describe<N: Dom>(node: Option<&N>) -> String {
+    match node {
+        None => "nothing".to_owned(),
+        Some(node) => match node.kind() {
+            NodeKind::Element => format!("<{}>", node.node_name()),
+            NodeKind::Text => format!("text {:?}", node.node_value().unwrap_or_default()),
+            NodeKind::Comment => format!("<!--{}-->", node.node_value().unwrap_or_default()),
+            _ => node.node_name(),
+        },
+    }
+}
+
+fn mismatch<N: Dom>(path: &[u32], expected: Option<&N>, found: Option<&N>) -> Stage0Error {
+    Stage0Error::HydrationMismatch {
+        path: path.to_vec(),
+        expected: describe(expected),
+        found: describe(found),
+    }
+}
+
+fn is_separator<N: Dom>(node: Option<&N>) -> bool {
+    node.is_some_and(|node| {
+        node.kind() == NodeKind::Comment && node.node_value().unwrap_or_default().is_empty()
+    })
+}
+
+fn same_kind<N: Dom>(template: &N, live: &N) -> bool {
+    template.kind() == live.kind()
+        && (template.kind() != NodeKind::Element || template.node_name() == live.node_name())
+}
+
+// Nothing is changed in the live DOM until the whole tree has matched, so the changes
+// are recorded as they are found and applied by `hydrate` at the end.
+struct Hydrator<'a, N> {
+    refs: &'a [Ref],
+    nodes: Vec<Option<N>>,
+    // Missing text nodes, with the parent and the node to insert them before.
+    texts: Vec<(N, N, Option<N>)>,
+    // Separators to remove, with their parent.
+    separators: Vec<(N, N)>,
+}
+
+impl<N: Dom> Hydrator<'_, N> {
+    fn visit(&mut self, template: &N, live: &N, path: &mut Vec<u32>) -> Result<(), Stage0Error> {
+        for (i, r) in self.refs.iter().enumerate() {
+            if r.path == *path {
+                self.nodes[i] = Some(live.clone());
+            }
+        }
+        if template.kind() == NodeKind::Element || template.kind() == NodeKind::Fragment {
+            self.visit_children(template, live, path)?;
+        }
+        Ok(())
+    }
+
+    // Pairs the template's children with the live ones. The server separates adjacent
+    // text with `<!---->` and drops empty text entirely, so separators are removed and
+    // missing text nodes recreated; anchor content is skipped up to the closing comment.
+    // Only text refs can hold other text than the template, and only they or empty text
+    // can be missing. A created text node isn't in the live DOM yet, so `found` stays
+    // where it is.
+    fn visit_children(
+        &mut self,
+        template: &N,
+        live: &N,
+        path: &mut Vec<u32>,
+    ) -> Result<(), Stage0Error> {
+        let mut expected = template.first_child();
+        let mut found = live.first_child();
+        let mut idx = 0;
+        let mut after_text = false;
+        while let Some(current) = expected {
+            let is_text = current.kind() == NodeKind::Text;
+            if is_text && after_text && is_separator(found.as_ref()) {
+                let separator = found.take().unwrap();
+                found = separator.next_sibling();
+                self.separators.push((live.clone(), separator));
+            }
+            path.push(idx);
+            let dynamic = is_text
+                && self
+                    .refs
+                    .iter()
+                    .any(|r| r.attr.is_none() && r.path == *path);
+            let value = current.node_value().unwrap_or_default();
+            let mut created = false;
+            let paired = match found {
+                Some(ref node)
+                    if same_kind(&current, node)
+                        && (!is_text || dynamic || node.node_value() == Some(value.clone())) =>
+                {
+                    node.clone()
+                }
+                _ if dynamic || (is_text && value.is_empty()) => {
+                    let text = live.create_text("")?;
+                    self.texts.push((live.clone(), text.clone(), found.clone()));
+                    created = true;
+                    text
+                }
+                _ => return Err(mismatch(path, Some(&current), found.as_ref())),
+            };
+
+            self.visit(&current, &paired, path)?;
+            path.pop();
+
+            if !created {
+                found = paired.next_sibling();
+            }
+            let next = current.next_sibling();
+            if current.kind() == NodeKind::Comment {
+                let end = next.as_ref().and_then(|node| node.node_value());
+                let name = current.node_value().unwrap_or_default();
+                if end.as_deref() == Some(format!("/{}", name).as_str()) {
+                    while let Some(node) = found.clone() {
+                        if node.kind() == NodeKind::Comment && node.node_value() == end {
+                            break;
+                        }
+                        found = node.next_sibling();
+                    }
+                }
+            }
+            after_text = is_text;
+            expected = next;
+            idx += 1;
+        }
+        if found.is_some() {
+            path.push(idx);
+            return Err(mismatch(path, None, found.as_ref()));
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn hydrate<N: Dom>(
+    template: &N,
+    live: &N,
+    refs: &[Ref],
+) -> Result<HashMap<String, N>, Stage0Error> {
+    let mut hydrator = Hydrator {
+        refs,
+        nodes: vec![None; refs.len()],
+        texts: Vec::new(),
+        separators: Vec::new(),
+    };
+    let mut path = Vec::new();
+    // A fragment's roots are matched against the children of `live`.
+    if template.kind() != NodeKind::Fragment && !same_kind(template, live) {
+        return Err(mismatch(&path, Some(template), Some(live)));
+    }
+    hydrator.visit(template, live, &mut path)?;
+
+    let mut nodes = HashMap::new();
+    for (r, node) in refs.iter().zip(hydrator.nodes) {
+        let node = node.ok_or_else(|| Stage0Error::MissingRef(r.ref_.clone()))?;
+        let node = match &r.attr {
+            Some(attr) => {
+                node.attribute_node(attr)
+                    .ok_or_else(|| Stage0Error::HydrationMismatch {
+                        path: r.path.clone(),
+                        expected: format!("`{}` attribute", attr),
+                        found: "nothing".to_owned(),
+                    })?
+            }
+            None => node,
+        };
+        nodes.insert(r.ref_.clone(), node);
+    }
+
+    for (parent, text, before) in hydrator.texts {
+        parent.insert_before(&text, before.as_ref())?;
+    }
+    for (parent, separator) in hydrator.separators {
+        parent.remove_child(&separator)?;
+    }
+    Ok(nodes)
+}
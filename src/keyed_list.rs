@@ -0,0 +1,299 @@
+use super::dom::Dom;
+use super::reconcile::{place, prepare, remove_later, ReconcileStats};
+use super::Stage0Error;
+use std::hash::Hash;
+use web_sys::Node;
+
+type CreateNode<T, N> = Box<dyn FnMut(&mut T) -> Result<N, Stage0Error>>;
+type UpdateNode<T, N> = Box<dyn FnMut(&N, &mut T) -> Result<(), Stage0Error>>;
+type RemoveNode<N> = Box<dyn FnMut(&N, Box<dyn FnOnce()>)>;
+
+/// A keyed list rendered into `parent`, which remembers the keys and nodes it rendered
+/// last so each `set_items` only has to be given the new items.
+pub struct KeyedList<T, K, N = Node> {
+    parent: N,
+    before_node: Option<N>,
+    after_node: Option<N>,
+    keys: Vec<K>,
+    nodes: Vec<N>,
+    get_key: Box<dyn Fn(&T) -> K>,
+    create_node: CreateNode<T, N>,
+    update_node: UpdateNode<T, N>,
+    remove_node: Option<RemoveNode<N>>,
+}
+
+impl<T, K: Eq + Hash, N: Dom + 'static> KeyedList<T, K, N> {
+    /// An empty list rendered into `parent`, which shouldn't have any children yet.
+    pub fn new(
+        parent: N,
+        get_key: impl Fn(&T) -> K + 'static,
+        mut create_node: impl FnMut(&mut T) -> N + 'static,
+        mut update_node: impl FnMut(&N, &mut T) + 'static,
+    ) -> KeyedList<T, K, N> {
+        KeyedList::try_new(
+            parent,
+            get_key,
+            move |item| Ok(create_node(item)),
+            move |node, item| {
+                update_node(node, item);
+                Ok(())
+            },
+        )
+    }
+
+    /// Like `new`, but `create_node` and `update_node` can fail, see `set_items`.
+    pub fn try_new(
+        parent: N,
+        get_key: impl Fn(&T) -> K + 'static,
+        create_node: impl FnMut(&mut T) -> Result<N, Stage0Error> + 'static,
+        update_node: impl FnMut(&N, &mut T) -> Result<(), Stage0Error> + 'static,
+    ) -> KeyedList<T, K, N> {
+        KeyedList {
+            parent,
+            before_node: None,
+            after_node: None,
+            keys: Vec::new(),
+            nodes: Vec::new(),
+            get_key: Box::new(get_key),
+            create_node: Box::new(create_node),
+            update_node: Box::new(update_node),
+            remove_node: None,
+        }
+    }
+
+    /// Renders the list between `before_node` and `after_node` instead, like the
+    /// arguments to `reconcile`, e.g. those of an `Anchor`.
+    pub fn with_anchors(mut self, before_node: Option<N>, after_node: Option<N>) -> Self {
+        self.before_node = before_node;
+        self.after_node = after_node;
+        self
+    }
+
+    /// Hands removed nodes to `on_remove` instead of detaching them right away, e.g. to
+    /// play an exit animation first. The node stays where it is until `done` is called,
+    /// and later updates place the remaining items around it.
+    pub fn on_remove(mut self, on_remove: impl FnMut(&N, Box<dyn FnOnce()>) + 'static) -> Self {
+        self.remove_node = Some(Box::new(on_remove));
+        self
+    }
+
+    pub fn keys(&self) -> &[K] {
+        &self.keys
+    }
+
+    /// The rendered nodes, in the same order as `keys`.
+    pub fn nodes(&self) -> &[N] {
+        &self.nodes
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Reconciles the rendered list with `items`.
+    ///
+    /// `create_node` and `update_node` are called for every item before the DOM is
+    /// changed, so if one fails the list is left as it was, apart from the nodes already
+    /// updated. If changing the DOM fails part way, the list is cleared so that the next
+    /// call starts over from an empty region.
+    pub fn set_items(&mut self, items: &mut [T]) -> Result<ReconcileStats, Stage0Error> {
+        let (new_ids, prepared) = prepare(
+            &self.keys,
+            &self.nodes,
+            items,
+            &self.get_key,
+            &mut self.create_node,
+            &mut self.update_node,
+        )?;
+
+        let remove_node = &mut self.remove_node;
+        let result = place(
+            &self.parent,
+            &self.keys,
+            &self.nodes,
+            &new_ids,
+            &prepared,
+            self.after_node.clone(),
+            |parent: &N, node: &N| match remove_node {
+                Some(remove_node) => {
+                    remove_later(parent, node, remove_node);
+                    Ok(())
+                }
+                None => parent.remove_child(node),
+            },
+        );
+        match result {
+            Ok(_) => {
+                self.keys = new_ids;
+                self.nodes = prepared;
+            }
+            Err(_) => {
+                self.keys.clear();
+                self.nodes.clear();
+                // The error that got us here is more useful than one from cleaning up.
+                let _ = self.clear();
+            }
+        }
+        result
+    }
+
+    // Removes everything between the anchors.
+    fn clear(&self) -> Result<(), Stage0Error> {
+        let mut node = match &self.before_node {
+            Some(before_node) => before_node.next_sibling(),
+            None => self.parent.first_child(),
+        };
+        while let Some(current) = node {
+            if self
+                .after_node
+                .as_ref()
+                .is_some_and(|after| current.is_same_node(after))
+            {
+                break;
+            }
+            node = current.next_sibling();
+            self.parent.remove_child(&current)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KeyedList;
+    use crate::dom::{Dom, MemoryNode};
+    use crate::reconcile::ReconcilePath;
+    use crate::Stage0Error;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn text_list(parent: &MemoryNode) -> KeyedList<(u32, &'static str), u32, MemoryNode> {
+        KeyedList::new(
+            parent.clone(),
+            |item: &(u32, &str)| item.0,
+            |item| MemoryNode::text(item.1),
+            |node, item| node.set_node_value(Some(item.1)),
+        )
+    }
+
+    #[test]
+    fn keyed_list_tests() {
+        let parent = MemoryNode::element("ul");
+        let mut list = text_list(&parent);
+        assert!(list.is_empty());
+
+        let stats = list.set_items(&mut [(1, "a"), (2, "b"), (3, "c")]).unwrap();
+        assert_eq!(stats.path, ReconcilePath::Create);
+        assert_eq!(parent.to_html(), "<ul>abc</ul>");
+        assert_eq!(list.keys(), &[1, 2, 3]);
+        let b = list.nodes()[1].clone();
+
+        let stats = list.set_items(&mut [(3, "C"), (2, "B"), (4, "d")]).unwrap();
+        assert_eq!((stats.created, stats.updated, stats.removed), (1, 2, 1));
+        assert_eq!(parent.to_html(), "<ul>CBd</ul>");
+        assert_eq!(list.keys(), &[3, 2, 4]);
+        assert_eq!(list.nodes().len(), 3);
+        assert_eq!(list.nodes()[1], b);
+        for (node, value) in list.nodes().iter().zip(["C", "B", "d"].iter()) {
+            assert_eq!(node.node_value().as_deref(), Some(*value));
+        }
+
+        list.set_items(&mut []).unwrap();
+        assert_eq!(parent.to_html(), "<ul></ul>");
+        assert!(list.is_empty());
+        assert!(list.nodes().is_empty());
+    }
+
+    #[test]
+    fn keyed_list_on_remove_tests() {
+        let parent = MemoryNode::element("ul");
+        type Leaving = Vec<(MemoryNode, Box<dyn FnOnce()>)>;
+        let leaving: Rc<RefCell<Leaving>> = Rc::default();
+        let mut list = {
+            let leaving = leaving.clone();
+            text_list(&parent).on_remove(move |node, done| {
+                leaving.borrow_mut().push((node.clone(), done));
+            })
+        };
+
+        list.set_items(&mut [(1, "a"), (2, "b"), (3, "c")]).unwrap();
+        let stats = list.set_items(&mut [(1, "a"), (3, "c")]).unwrap();
+        assert_eq!(stats.removed, 1);
+        assert_eq!(parent.to_html(), "<ul>abc</ul>");
+        assert_eq!(leaving.borrow()[0].0.node_value().as_deref(), Some("b"));
+
+        // The leaving node doesn't count as rendered.
+        list.set_items(&mut [(3, "c"), (4, "d"), (1, "a")]).unwrap();
+        assert_eq!(list.keys(), &[3, 4, 1]);
+        let (_, done) = leaving.borrow_mut().remove(0);
+        done();
+        assert_eq!(parent.to_html(), "<ul>cda</ul>");
+        for (node, value) in list.nodes().iter().zip(["c", "d", "a"].iter()) {
+            assert_eq!(node.node_value().as_deref(), Some(*value));
+        }
+
+        list.set_items(&mut []).unwrap();
+        assert_eq!(parent.to_html(), "<ul>cda</ul>");
+        let leaving: Vec<_> = leaving.borrow_mut().drain(..).collect();
+        assert_eq!(leaving.len(), 3);
+        for (_, done) in leaving {
+            done();
+        }
+        assert_eq!(parent.to_html(), "<ul></ul>");
+    }
+
+    #[test]
+    fn keyed_list_try_tests() {
+        let parent = MemoryNode::element("ul");
+        let mut list = KeyedList::try_new(
+            parent.clone(),
+            |item: &(u32, &str)| item.0,
+            |item| match item.1 {
+                "" => Err(Stage0Error::EmptyTemplate),
+                value => Ok(MemoryNode::text(value)),
+            },
+            |node, item| {
+                node.set_node_value(Some(item.1));
+                Ok(())
+            },
+        );
+
+        list.set_items(&mut [(1, "a"), (2, "b")]).unwrap();
+        let result = list.set_items(&mut [(2, "b"), (3, ""), (1, "a")]);
+        assert!(matches!(result, Err(Stage0Error::EmptyTemplate)));
+        assert_eq!(parent.to_html(), "<ul>ab</ul>");
+        assert_eq!(list.keys(), &[1, 2]);
+
+        let result = list.set_items(&mut [(2, "b"), (2, "c")]);
+        assert_eq!(result.unwrap_err(), Stage0Error::DuplicateKey(1));
+        assert_eq!(list.keys(), &[1, 2]);
+
+        list.set_items(&mut [(2, "b"), (3, "c"), (1, "a")]).unwrap();
+        assert_eq!(parent.to_html(), "<ul>bca</ul>");
+        assert_eq!(list.keys(), &[2, 3, 1]);
+    }
+
+    #[test]
+    fn keyed_list_anchor_tests() {
+        let parent = MemoryNode::parse("<ul><li>head</li><!--items--><!--/items--></ul>").unwrap();
+        let start = parent.first_child().unwrap().next_sibling().unwrap();
+        let end = start.next_sibling().unwrap();
+        let mut list = text_list(&parent).with_anchors(Some(start), Some(end));
+
+        list.set_items(&mut [(1, "a"), (2, "b")]).unwrap();
+        assert_eq!(
+            parent.to_html(),
+            "<ul><li>head</li><!--items-->ab<!--/items--></ul>"
+        );
+        list.set_items(&mut [(2, "b")]).unwrap();
+        assert_eq!(
+            parent.to_html(),
+            "<ul><li>head</li><!--items-->b<!--/items--></ul>"
+        );
+        assert_eq!(list.nodes()[0].node_value().as_deref(), Some("b"));
+    }
+}
@@ -1,38 +1,119 @@
+// Lets `h!` expand to `::stage0::...` paths inside this crate too.
+extern crate self as stage0;
+
+mod anchor;
+pub mod dom;
+mod error;
+mod hydrate;
+mod keyed_list;
 pub mod reconcile;
+pub mod ssr;
 pub mod synthetic_events;
 
 use lazy_static::lazy_static;
+use smallvec::SmallVec;
 use std::collections::HashMap;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::{Element, Node};
 
-fn collector(node: &Node) -> Result<Option<String>, JsValue> {
-    if node.node_type() != Node::TEXT_NODE {
-        if let Some(el) = node.dyn_ref::<Element>() {
-            if el.has_attributes() {
-                let attrs = el.attributes();
-                for i in 0..attrs.length() {
-                    if let Some(attr) = attrs.item(i) {
-                        let name = attr.name();
-                        if name.as_str().starts_with('#') {
-                            el.remove_attribute(&name)?;
-                            return Ok(Some(name.as_str().trim_start_matches('#').to_owned()));
-                        }
+use dom::{Dom, NodeKind};
+use stage0_parser::{
+    check_sigil, collapse_text, find_text_ref, is_block, is_ref_name, marker_ref, unescape,
+    BLOCK_ELEMENTS, ESCAPABLE_RAW_TEXT_ELEMENTS, PREFORMATTED_ELEMENTS, RAW_TEXT_ELEMENTS,
+};
+
+pub use anchor::Anchor;
+pub use error::Stage0Error;
+pub use keyed_list::KeyedList;
+pub use stage0_macros::{h, Refs};
+pub use stage0_parser::{CompileOptions, Namespace};
+
+fn collector<N: Dom>(node: &N, sigil: char) -> Result<Option<String>, Stage0Error> {
+    match node.kind() {
+        NodeKind::Comment => {
+            if let Some(node_value) = node.node_value() {
+                if let Some(name) = node_value.trim().strip_prefix(sigil) {
+                    if is_ref_name(name) {
+                        node.set_node_value(Some(name));
+                        return Ok(Some(name.to_owned()));
                     }
                 }
             }
+            Ok(None)
         }
-        Ok(None)
-    } else {
-        if let Some(node_value) = node.node_value() {
-            if node_value.as_str().starts_with('#') {
-                node.set_node_value(None);
-                return Ok(Some(node_value.as_str().trim_start_matches('#').to_owned()));
+        NodeKind::Element => {
+            let mut marker = None;
+            for attr in node.attribute_names() {
+                if let Some(name) = marker_ref(&attr, sigil)? {
+                    if marker.is_some() {
+                        return Err(Stage0Error::Parse(format!(
+                            "`<{}>` has more than one ref",
+                            node.node_name()
+                        )));
+                    }
+                    marker = Some((attr.clone(), name.to_owned()));
+                }
+            }
+            match marker {
+                Some((attr, name)) => {
+                    node.remove_attribute(&attr)?;
+                    Ok(Some(name))
+                }
+                None => Ok(None),
             }
         }
-        Ok(None)
+        NodeKind::Text => {
+            if let Some(node_value) = node.node_value() {
+                let text_ref = find_text_ref(&node_value, sigil);
+                let literal = match text_ref {
+                    Some((start, _)) => &node_value[..start],
+                    None => &node_value[..],
+                };
+                if let Some((start, name)) = text_ref {
+                    // Split the placeholder into its own text node. Whatever follows it is
+                    // split off too and visited as the next sibling.
+                    let end = start + sigil.len_utf8() + name.len();
+                    if start > 0 || end < node_value.len() {
+                        let parent = node.parent_node().ok_or_else(|| {
+                            Stage0Error::Parse("text interpolation needs a parent node".to_owned())
+                        })?;
+                        let split_at = if start > 0 { start } else { end };
+                        let rest = node.create_text(&node_value[split_at..])?;
+                        parent.insert_before(&rest, node.next_sibling().as_ref())?;
+                        node.set_node_value(Some(&node_value[..split_at]));
+                    }
+                    if start == 0 {
+                        node.set_node_value(None);
+                        return Ok(Some(name.to_owned()));
+                    }
+                }
+                if let Some(literal) = unescape(literal, sigil) {
+                    node.set_node_value(Some(&literal));
+                }
+            }
+            Ok(None)
+        }
+        _ => Ok(None),
+    }
+}
+
+// Collects `attr="#name"` bindings, blanking each bound value. Values like `#/active`
+// aren't ref names and are left alone, while a leading `##` is unescaped to `#`.
+fn attr_collector<N: Dom>(el: &N, sigil: char) -> Result<Vec<(String, String)>, Stage0Error> {
+    let mut bindings = Vec::new();
+    for name in el.attribute_names() {
+        let value = el.get_attribute(&name).unwrap_or_default();
+        match value.strip_prefix(sigil) {
+            Some(rest) if rest.starts_with(sigil) => el.set_attribute(&name, rest)?,
+            Some(ref_) if is_ref_name(ref_) => {
+                bindings.push((name.clone(), ref_.to_owned()));
+                el.set_attribute(&name, "")?;
+            }
+            _ => {}
+        }
     }
+    Ok(bindings)
 }
 
 struct Document(web_sys::Document);
@@ -49,69 +130,195 @@ lazy_static! {
     };
 }
 
-struct TreeWalker(web_sys::TreeWalker);
-
-unsafe impl Sync for TreeWalker {}
-
-lazy_static! {
-    static ref TREE_WALKER: TreeWalker = {
-        let tree_walker = DOCUMENT.0.create_tree_walker(&DOCUMENT.0).unwrap();
-        TreeWalker(tree_walker)
-    };
+pub struct Ref {
+    path: Vec<u32>,
+    attr: Option<String>,
+    ref_: String,
 }
 
-fn roll(mut idx: usize) -> Result<Node, JsValue> {
-    while idx > 1 {
-        TREE_WALKER.0.next_node()?;
-        idx -= 1;
+// Follows a path of child indices from `root`, using only firstChild/nextSibling.
+fn resolve<N: Dom>(root: &N, path: &[u32], attr: Option<&str>) -> Option<N> {
+    let mut node = root.clone();
+    for &idx in path {
+        node = node.first_child()?;
+        for _ in 0..idx {
+            node = node.next_sibling()?;
+        }
+    }
+    match attr {
+        Some(attr) => node.attribute_node(attr),
+        None => Some(node),
     }
-    Ok(TREE_WALKER.0.current_node())
 }
 
-pub struct Ref {
-    idx: usize,
+fn push_ref(
+    indices: &mut Vec<Ref>,
+    path: &[u32],
+    attr: Option<String>,
     ref_: String,
+) -> Result<(), Stage0Error> {
+    if ref_.is_empty() {
+        return Err(Stage0Error::Parse("empty ref name".to_owned()));
+    }
+    if indices.iter().any(|r| r.ref_ == ref_) {
+        return Err(Stage0Error::DuplicateRef(ref_));
+    }
+    indices.push(Ref {
+        path: path.to_vec(),
+        attr,
+        ref_,
+    });
+    Ok(())
 }
 
-fn gen_path(node: &Node) -> Result<Vec<Ref>, JsValue> {
-    TREE_WALKER.0.set_current_node(node);
-
-    let mut indices = Vec::new();
-    let mut idx = 0;
+// Closes a `<!--#name-->` anchor with a `<!--/name-->` comment so the region between
+// them stays well defined once content is mounted.
+fn insert_anchor_end<N: Dom>(node: &N, ref_: &str) -> Result<(), Stage0Error> {
+    let parent = node.parent_node().ok_or_else(|| {
+        Stage0Error::Parse(format!("anchor `#{}` can't be the only root node", ref_))
+    })?;
+    let end = node.create_comment(&format!("/{}", ref_))?;
+    parent.insert_before(&end, node.next_sibling().as_ref())?;
+    Ok(())
+}
 
-    match collector(node)? {
-        Some(ref_) => {
-            indices.push(Ref { idx: idx + 1, ref_ });
-            idx = 1;
+fn walk<N: Dom>(
+    node: &N,
+    sigil: char,
+    path: &mut Vec<u32>,
+    indices: &mut Vec<Ref>,
+) -> Result<(), Stage0Error> {
+    if let Some(ref_) = collector(node, sigil)? {
+        if node.kind() == NodeKind::Comment {
+            insert_anchor_end(node, &ref_)?;
+        }
+        push_ref(indices, path, None, ref_)?;
+    }
+    if node.kind() == NodeKind::Element {
+        for (attr, ref_) in attr_collector(node, sigil)? {
+            push_ref(indices, path, Some(attr), ref_)?;
+        }
+        if RAW_TEXT_ELEMENTS.contains(&node.node_name().as_str()) {
+            return Ok(());
         }
-        None => idx += 1,
     }
 
-    while let Some(current) = TREE_WALKER.0.next_node()? {
-        match collector(&current)? {
-            Some(ref_) => {
-                indices.push(Ref { idx: idx + 1, ref_ });
-                idx = 1;
-            }
-            None => idx += 1,
+    let texts = count_texts(node);
+    let mut child = node.first_child();
+    let mut idx = 0;
+    while let Some(current) = child {
+        path.push(idx);
+        walk(&current, sigil, path, indices)?;
+        path.pop();
+        child = current.next_sibling();
+        idx += 1;
+    }
+    if node.kind() == NodeKind::Element
+        && ESCAPABLE_RAW_TEXT_ELEMENTS.contains(&node.node_name().as_str())
+        && count_texts(node) > texts
+    {
+        return Err(Stage0Error::Parse(format!(
+            "a ref in `<{}>` has to be its whole text",
+            node.node_name()
+        )));
+    }
+
+    Ok(())
+}
+
+fn count_texts<N: Dom>(node: &N) -> usize {
+    let mut count = 0;
+    let mut child = node.first_child();
+    while let Some(current) = child {
+        if current.kind() == NodeKind::Text {
+            count += 1;
         }
+        child = current.next_sibling();
     }
+    count
+}
 
+fn gen_path<N: Dom>(node: &N, sigil: char) -> Result<Vec<Ref>, Stage0Error> {
+    let mut indices = Vec::new();
+    walk(node, sigil, &mut Vec::new(), &mut indices)?;
     Ok(indices)
 }
 
-pub struct Template {
-    node: Node,
+pub type IndexedRefs<N = Node> = SmallVec<[N; 8]>;
+
+/// A compiled template, generic over the `Dom` backend it was built on.
+pub struct Template<N = Node> {
+    node: N,
     ref_paths: Vec<Ref>,
 }
 
 impl Template {
-    pub fn collect(&self) -> Result<HashMap<String, Node>, JsValue> {
+    pub fn collect_into<T: Refs>(&self) -> Result<T, Stage0Error> {
+        T::from_refs(self.collect()?)
+    }
+}
+
+impl<N: Dom> Template<N> {
+    pub fn node(&self) -> &N {
+        &self.node
+    }
+
+    pub fn collect(&self) -> Result<HashMap<String, N>, Stage0Error> {
+        self.collect_from(&self.node)
+    }
+
+    /// Deep-clones the template node and resolves its refs against the clone, so one
+    /// compiled template can be stamped out many times without reparsing.
+    pub fn instantiate(&self) -> Result<Instance<N>, Stage0Error> {
+        let node = self.node.clone_deep()?;
+        let refs = self.collect_from(&node)?;
+        Ok(Instance { node, refs })
+    }
+
+    /// Resolves the refs against `existing_root`, DOM already in the page such as the
+    /// output of `ssr::render`, instead of the template's own nodes. For a template with
+    /// several roots, `existing_root` is the element containing them. The `<!---->`
+    /// separators the server puts between adjacent text nodes are removed and text refs
+    /// it dropped for being empty are recreated. Any other text has to match the
+    /// template, and a `HydrationMismatch` is returned where the live tree diverges.
+    pub fn hydrate(&self, existing_root: &N) -> Result<HashMap<String, N>, Stage0Error> {
+        hydrate::hydrate(&self.node, existing_root, &self.ref_paths)
+    }
+
+    /// Resolves the refs into a list ordered like their declarations in the template,
+    /// without hashing or allocating for templates with up to eight refs. Look up
+    /// positions once with `ref_index`.
+    pub fn collect_indexed(&self) -> Result<IndexedRefs<N>, Stage0Error> {
+        self.collect_indexed_from(&self.node)
+    }
+
+    /// Like `instantiate`, but resolves the clone's refs with `collect_indexed`.
+    pub fn instantiate_indexed(&self) -> Result<(N, IndexedRefs<N>), Stage0Error> {
+        let node = self.node.clone_deep()?;
+        let refs = self.collect_indexed_from(&node)?;
+        Ok((node, refs))
+    }
+
+    pub fn ref_index(&self, name: &str) -> Option<usize> {
+        self.ref_paths.iter().position(|r| r.ref_ == name)
+    }
+
+    fn collect_indexed_from(&self, root: &N) -> Result<IndexedRefs<N>, Stage0Error> {
+        self.ref_paths
+            .iter()
+            .map(|ref_path| {
+                resolve(root, &ref_path.path, ref_path.attr.as_deref())
+                    .ok_or_else(|| Stage0Error::MissingRef(ref_path.ref_.clone()))
+            })
+            .collect()
+    }
+
+    fn collect_from(&self, root: &N) -> Result<HashMap<String, N>, Stage0Error> {
         let mut refs = HashMap::new();
-        TREE_WALKER.0.set_current_node(&self.node);
 
         for ref_path in self.ref_paths.iter() {
-            let ref_node = roll(ref_path.idx)?;
+            let ref_node = resolve(root, &ref_path.path, ref_path.attr.as_deref())
+                .ok_or_else(|| Stage0Error::MissingRef(ref_path.ref_.clone()))?;
             refs.insert(ref_path.ref_.clone(), ref_node);
         }
 
@@ -119,9 +326,37 @@ impl Template {
     }
 }
 
-impl Into<Node> for Template {
-    fn into(self) -> Node {
-        self.node
+/// A struct of typed template refs, usually implemented with `#[derive(Refs)]`.
+pub trait Refs: Sized {
+    fn from_refs(refs: HashMap<String, Node>) -> Result<Self, Stage0Error>;
+}
+
+#[doc(hidden)]
+pub mod __private {
+    pub use std::collections::HashMap;
+    pub use web_sys::Node;
+
+    use super::Stage0Error;
+    use wasm_bindgen::JsCast;
+
+    pub fn take_ref<T: JsCast>(
+        refs: &mut HashMap<String, Node>,
+        name: &str,
+    ) -> Result<T, Stage0Error> {
+        let node = refs
+            .remove(name)
+            .ok_or_else(|| Stage0Error::MissingRef(name.to_owned()))?;
+        node.dyn_into::<T>()
+            .map_err(|_| Stage0Error::WrongNodeType {
+                name: name.to_owned(),
+                expected: std::any::type_name::<T>(),
+            })
+    }
+}
+
+impl From<Template> for Node {
+    fn from(template: Template) -> Node {
+        template.node
     }
 }
 
@@ -137,6 +372,46 @@ impl AsRef<JsValue> for Template {
     }
 }
 
+pub struct Instance<N = Node> {
+    node: N,
+    refs: HashMap<String, N>,
+}
+
+impl Instance {
+    pub fn collect_into<T: Refs>(&mut self) -> Result<T, Stage0Error> {
+        T::from_refs(self.collect())
+    }
+}
+
+impl<N: Dom> Instance<N> {
+    pub fn node(&self) -> &N {
+        &self.node
+    }
+
+    /// Takes the refs resolved by `Template::instantiate`. Later calls return an empty map.
+    pub fn collect(&mut self) -> HashMap<String, N> {
+        std::mem::take(&mut self.refs)
+    }
+}
+
+impl From<Instance> for Node {
+    fn from(instance: Instance) -> Node {
+        instance.node
+    }
+}
+
+impl AsRef<Node> for Instance {
+    fn as_ref(&self) -> &Node {
+        &self.node
+    }
+}
+
+impl AsRef<JsValue> for Instance {
+    fn as_ref(&self) -> &JsValue {
+        self.node.as_ref()
+    }
+}
+
 struct CompilerTemplate(web_sys::HtmlTemplateElement);
 
 unsafe impl Sync for CompilerTemplate {}
@@ -152,27 +427,215 @@ lazy_static! {
     };
 }
 
-pub fn h(value: &str) -> Result<Template, JsValue> {
+fn element_name<N: Dom>(node: &N) -> Option<String> {
+    if node.kind() == NodeKind::Element {
+        Some(node.node_name())
+    } else {
+        None
+    }
+}
+
+fn collapse_whitespace<N: Dom>(node: &N, block: bool) -> Result<(), Stage0Error> {
+    let mut child = node.first_child();
+    while let Some(current) = child {
+        child = current.next_sibling();
+        if current.kind() == NodeKind::Element {
+            let name = current.node_name();
+            if !PREFORMATTED_ELEMENTS.contains(&name.as_str()) {
+                collapse_whitespace(&current, BLOCK_ELEMENTS.contains(&name.as_str()))?;
+            }
+        } else if current.kind() == NodeKind::Text {
+            let value = current.node_value().unwrap_or_default();
+            let collapsed = collapse_text(
+                &value,
+                is_block(current.previous_sibling().as_ref(), block, element_name),
+                is_block(child.as_ref(), block, element_name),
+            );
+            if collapsed.is_empty() {
+                node.remove_child(&current)?;
+            } else if collapsed != value {
+                current.set_node_value(Some(&collapsed));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parses `value` into a template. Placeholders inside text such as `#count items left`
+/// are split into their own `Text` refs, except in `<script>` and `<style>`; a ref in
+/// `<textarea>` or `<title>` has to be the element's whole text. Several top-level nodes
+/// (or a lone text node) are wrapped in a `DocumentFragment`, which empties when
+/// inserted, so stamp it out with `Template::instantiate` instead of inserting the
+/// template node itself.
+pub fn h(value: &str) -> Result<Template, Stage0Error> {
+    h_ns(Namespace::Html, value)
+}
+
+/// Like `h`, but compiles the template with `options`.
+pub fn h_with(value: &str, options: &CompileOptions) -> Result<Template, Stage0Error> {
+    h_ns_with(Namespace::Html, value, options)
+}
+
+pub fn h_svg(value: &str) -> Result<Template, Stage0Error> {
+    h_ns(Namespace::Svg, value)
+}
+
+/// Like `h`, but parses `value` as the children of an `<svg>` or `<math>` element so that
+/// bare fragments like `<circle #dot></circle>` are created in that namespace.
+pub fn h_ns(namespace: Namespace, value: &str) -> Result<Template, Stage0Error> {
+    h_ns_with(namespace, value, &CompileOptions::default())
+}
+
+/// Like `h_ns`, but compiles the template with `options`.
+pub fn h_ns_with(
+    namespace: Namespace,
+    value: &str,
+    options: &CompileOptions,
+) -> Result<Template, Stage0Error> {
+    compile_with(parse_ns(namespace, value)?, options)
+}
+
+fn parse_ns(namespace: Namespace, value: &str) -> Result<Node, Stage0Error> {
     // TODO use String.raw ?
-    COMPILER_TEMPLATE.0.set_inner_html(value.trim());
-    let content = COMPILER_TEMPLATE
-        .0
-        .content()
-        .first_child()
-        .expect("first child");
-    compile(content)
+    let value = value.trim();
+    let content: Node = COMPILER_TEMPLATE.0.content().into();
+    let container = match namespace {
+        Namespace::Html => {
+            COMPILER_TEMPLATE.0.set_inner_html(value);
+            content
+        }
+        Namespace::Svg | Namespace::MathMl => {
+            let tag = if namespace == Namespace::Svg {
+                "svg"
+            } else {
+                "math"
+            };
+            COMPILER_TEMPLATE
+                .0
+                .set_inner_html(&format!("<{0}>{1}</{0}>", tag, value));
+            content
+                .first_child()
+                .ok_or_else(|| Stage0Error::EmptyTemplate)?
+        }
+    };
+
+    let node = match container.first_child() {
+        None => return Err(Stage0Error::EmptyTemplate),
+        // A lone text root stays in a fragment so interpolation can split it.
+        Some(first) if first.next_sibling().is_none() && first.node_type() != Node::TEXT_NODE => {
+            container.remove_child(&first)?
+        }
+        Some(_) => {
+            let fragment = DOCUMENT.0.create_document_fragment();
+            while let Some(child) = container.first_child() {
+                fragment.append_child(&child)?;
+            }
+            fragment.into()
+        }
+    };
+    Ok(node)
+}
+
+/// Compiles a node built on any `Dom` backend into a template, collecting its refs.
+pub fn compile<N: Dom>(node: N) -> Result<Template<N>, Stage0Error> {
+    compile_with(node, &CompileOptions::default())
 }
 
-pub fn compile(node: Node) -> Result<Template, JsValue> {
-    let ref_paths = gen_path(&node)?;
+pub fn compile_with<N: Dom>(node: N, options: &CompileOptions) -> Result<Template<N>, Stage0Error> {
+    let sigil = options.sigil;
+    check_sigil(sigil)?;
+    if options.collapse_whitespace {
+        let name = node.node_name();
+        let is_element = node.kind() == NodeKind::Element;
+        if !(is_element && PREFORMATTED_ELEMENTS.contains(&name.as_str())) {
+            let block = !is_element || BLOCK_ELEMENTS.contains(&name.as_str());
+            collapse_whitespace(&node, block)?;
+        }
+    }
+    let ref_paths = gen_path(&node, sigil)?;
+    Ok(Template { node, ref_paths })
+}
+
+// Node tree emitted by the `h!` macro, with ref markers already stripped.
+#[doc(hidden)]
+pub enum StaticNode {
+    Element(
+        &'static str,
+        &'static [(&'static str, &'static str)],
+        &'static [StaticNode],
+    ),
+    ElementNs(
+        &'static str,
+        &'static str,
+        &'static [(&'static str, &'static str)],
+        &'static [StaticNode],
+    ),
+    Text(&'static str),
+    Comment(&'static str),
+}
+
+fn build(node: &StaticNode) -> Result<Node, Stage0Error> {
+    match node {
+        StaticNode::Element(name, attrs, children) => {
+            build_element(DOCUMENT.0.create_element(name)?, attrs, children)
+        }
+        StaticNode::ElementNs(namespace, name, attrs, children) => build_element(
+            DOCUMENT.0.create_element_ns(Some(namespace), name)?,
+            attrs,
+            children,
+        ),
+        StaticNode::Text(value) => Ok(DOCUMENT.0.create_text_node(value).into()),
+        StaticNode::Comment(value) => Ok(DOCUMENT.0.create_comment(value).into()),
+    }
+}
+
+fn build_element(
+    el: Element,
+    attrs: &[(&str, &str)],
+    children: &[StaticNode],
+) -> Result<Node, Stage0Error> {
+    for (name, value) in attrs.iter() {
+        el.set_attribute(name, value)?;
+    }
+    for child in children.iter() {
+        el.append_child(&build(child)?)?;
+    }
+    Ok(el.into())
+}
+
+#[doc(hidden)]
+pub fn compile_static(
+    roots: &[StaticNode],
+    refs: &[(&[u32], Option<&str>, &str)],
+) -> Result<Template, Stage0Error> {
+    let node = match roots {
+        [] => return Err(Stage0Error::EmptyTemplate),
+        [root] if !matches!(root, StaticNode::Text(_)) => build(root)?,
+        _ => {
+            let fragment = DOCUMENT.0.create_document_fragment();
+            for root in roots.iter() {
+                fragment.append_child(&build(root)?)?;
+            }
+            fragment.into()
+        }
+    };
+    let ref_paths = refs
+        .iter()
+        .map(|&(path, attr, ref_)| Ref {
+            path: path.to_vec(),
+            attr: attr.map(ToOwned::to_owned),
+            ref_: ref_.to_owned(),
+        })
+        .collect();
     Ok(Template { node, ref_paths })
 }
 
 #[cfg(test)]
 mod tests {
-    use wasm_bindgen::JsCast;
+    use super::Refs;
+    use wasm_bindgen::{JsCast, JsValue};
     use wasm_bindgen_test::*;
-    use web_sys::{HtmlElement, Node};
+    use web_sys::{Attr, Element, HtmlElement, HtmlTemplateElement, Node, Text};
 
     wasm_bindgen_test_configure!(run_in_browser);
 
@@ -187,22 +650,45 @@ mod tests {
             el.set_inner_html("<span></span>");
             let test_node = node.first_child().unwrap();
             let test_el = test_node.unchecked_ref::<HtmlElement>();
-            assert_eq!(super::collector(test_el), Ok(None));
+            assert_eq!(super::collector::<Node>(test_el, '#'), Ok(None));
         }
 
         {
             el.set_inner_html("<span #test-attr></span>");
             let test_node = node.first_child().unwrap();
             let test_el = test_node.unchecked_ref::<HtmlElement>();
-            assert_eq!(super::collector(test_el), Ok(Some("test-attr".to_owned())));
+            assert_eq!(
+                super::collector::<Node>(test_el, '#'),
+                Ok(Some("test-attr".to_owned()))
+            );
         }
 
         {
             let text = document.create_text_node("#test-text");
-            assert_eq!(super::collector(&text), Ok(Some("test-text".to_owned())));
+            assert_eq!(
+                super::collector::<Node>(&text, '#'),
+                Ok(Some("test-text".to_owned()))
+            );
         }
     }
 
+    #[wasm_bindgen_test]
+    fn create_in_owner_document_tests() {
+        use super::dom::Dom;
+
+        let document = web_sys::window().unwrap().document().unwrap();
+        let template = document
+            .create_element("template")
+            .unwrap()
+            .unchecked_into::<HtmlTemplateElement>();
+        let content: Node = template.content().into();
+        let text = content.create_text("x").unwrap();
+        let comment = content.create_comment("x").unwrap();
+        assert_eq!(text.owner_document(), content.owner_document());
+        assert_eq!(comment.owner_document(), content.owner_document());
+        assert_ne!(text.owner_document(), Some(document));
+    }
+
     #[wasm_bindgen_test]
     fn h_tests() {
         {
@@ -242,4 +728,545 @@ mod tests {
         }
     }
 
+    #[wasm_bindgen_test]
+    fn h_macro_tests() {
+        {
+            let template = super::h!("<div></div>").unwrap();
+            let refs = template.collect().unwrap();
+            assert!(refs.is_empty());
+        }
+
+        {
+            let template = super::h!(
+                "
+                <div #foo>
+                    <span>#bar</span>
+                    <input type=checkbox #baz>
+                </div>
+                "
+            )
+            .unwrap();
+            let refs = template.collect().unwrap();
+            assert_eq!(refs.len(), 3);
+
+            let foo = refs.get("foo").unwrap();
+            assert_eq!(foo.node_name(), "DIV");
+
+            let bar = refs.get("bar").unwrap();
+            assert_eq!(bar.node_type(), Node::TEXT_NODE);
+            assert_eq!(bar.node_value(), Some("".to_owned()));
+
+            let baz = refs.get("baz").unwrap();
+            assert_eq!(baz.node_name(), "INPUT");
+            assert!(!baz.unchecked_ref::<HtmlElement>().has_attribute("#baz"));
+        }
+    }
+
+    #[derive(Refs)]
+    struct FooBarRefs {
+        foo: HtmlElement,
+        bar: Text,
+    }
+
+    #[derive(Refs)]
+    struct WrongRefs {
+        #[refs(name = "foo")]
+        _foo: HtmlTemplateElement,
+    }
+
+    #[derive(Refs)]
+    struct MissingRefs {
+        _baz: Node,
+    }
+
+    #[wasm_bindgen_test]
+    fn collect_into_tests() {
+        let template = super::h("<div #foo>#bar</div>").unwrap();
+
+        let refs = template.collect_into::<FooBarRefs>().unwrap();
+        assert_eq!(refs.foo.node_name(), "DIV");
+        assert_eq!(refs.bar.node_type(), Node::TEXT_NODE);
+
+        match template.collect_into::<WrongRefs>() {
+            Err(super::Stage0Error::WrongNodeType { name, .. }) => assert_eq!(name, "foo"),
+            _ => panic!("expected a wrong node type error"),
+        }
+        assert_eq!(
+            template.collect_into::<MissingRefs>().err(),
+            Some(super::Stage0Error::MissingRef("_baz".to_owned()))
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn instantiate_tests() {
+        let template = super::h("<div #foo>#bar</div>").unwrap();
+
+        let mut first = template.instantiate().unwrap();
+        let mut second = template.instantiate().unwrap();
+        let first_node = AsRef::<Node>::as_ref(&first).clone();
+        let second_node = AsRef::<Node>::as_ref(&second).clone();
+        assert!(!first_node.is_same_node(Some(&second_node)));
+
+        let first_refs = first.collect_into::<FooBarRefs>().unwrap();
+        assert!(first_refs.foo.is_same_node(Some(&first_node)));
+        assert!(first.collect().is_empty());
+
+        let second_refs = second.collect();
+        assert_eq!(second_refs.len(), 2);
+        let bar = second_refs.get("bar").unwrap();
+        assert!(second_node.contains(Some(bar)));
+
+        let template_node: &Node = template.as_ref();
+        let refs = template.collect().unwrap();
+        assert!(refs.get("foo").unwrap().is_same_node(Some(template_node)));
+    }
+
+    #[wasm_bindgen_test]
+    fn nested_collect_tests() {
+        let outer = super::h("<ul><li>a</li><li #item><b>#label</b></li></ul>").unwrap();
+        let inner = super::h("<p><i></i>#text</p>").unwrap();
+
+        let mut outer_refs = outer.collect().unwrap();
+        let inner_refs = inner.collect().unwrap();
+        let item = outer_refs.remove("item").unwrap();
+        let label = outer_refs.remove("label").unwrap();
+        let text = inner_refs.get("text").unwrap();
+
+        assert_eq!(item.node_name(), "LI");
+        assert!(label.parent_node().unwrap().is_same_node(item.first_child().as_ref()));
+        assert_eq!(text.node_type(), Node::TEXT_NODE);
+        assert_eq!(text.previous_sibling().unwrap().node_name(), "I");
+    }
+
+    #[wasm_bindgen_test]
+    fn multi_root_tests() {
+        assert!(super::h("").is_err());
+        assert!(super::h("  \n ").is_err());
+
+        {
+            let template = super::h("<tr #first></tr><tr><td>#cell</td></tr>").unwrap();
+            let node: &Node = template.as_ref();
+            assert_eq!(node.node_type(), Node::DOCUMENT_FRAGMENT_NODE);
+            let first = node.first_child().unwrap();
+            assert!(node.last_child().unwrap().is_same_node(first.next_sibling().as_ref()));
+
+            let mut instance = template.instantiate().unwrap();
+            let refs = instance.collect();
+            assert_eq!(refs.get("first").unwrap().node_name(), "TR");
+            assert_eq!(refs.get("cell").unwrap().node_type(), Node::TEXT_NODE);
+        }
+
+        {
+            let template = super::h!("#greeting<b #name></b>").unwrap();
+            let node: &Node = template.as_ref();
+            assert_eq!(node.node_type(), Node::DOCUMENT_FRAGMENT_NODE);
+
+            let refs = template.collect().unwrap();
+            assert_eq!(refs.get("greeting").unwrap().node_type(), Node::TEXT_NODE);
+            assert_eq!(refs.get("name").unwrap().node_name(), "B");
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn namespace_tests() {
+        const SVG: &str = "http://www.w3.org/2000/svg";
+
+        {
+            let template = super::h_svg("<circle #dot r='5'></circle>").unwrap();
+            let refs = template.collect().unwrap();
+            let dot = refs.get("dot").unwrap().unchecked_ref::<Element>();
+            assert_eq!(dot.namespace_uri(), Some(SVG.to_owned()));
+            assert!(!dot.has_attribute("#dot"));
+        }
+
+        {
+            let template = super::h_ns(super::Namespace::MathMl, "<mi>#x</mi>").unwrap();
+            let node: &Node = template.as_ref();
+            assert_eq!(
+                node.unchecked_ref::<Element>().namespace_uri(),
+                Some("http://www.w3.org/1998/Math/MathML".to_owned())
+            );
+            assert!(template.collect().unwrap().contains_key("x"));
+        }
+
+        {
+            let template = super::h!(svg, "<circle #dot/><rect #box/>").unwrap();
+            let refs = template.collect().unwrap();
+            let dot = refs.get("dot").unwrap().unchecked_ref::<Element>();
+            assert_eq!(dot.namespace_uri(), Some(SVG.to_owned()));
+            assert_eq!(refs.get("box").unwrap().node_name(), "rect");
+        }
+
+        {
+            let template =
+                super::h!("<div><svg #chart viewBox='0 0 10 10'><path/></svg></div>").unwrap();
+            let refs = template.collect().unwrap();
+            let chart = refs.get("chart").unwrap().unchecked_ref::<Element>();
+            assert_eq!(chart.namespace_uri(), Some(SVG.to_owned()));
+            assert_eq!(chart.get_attribute("viewBox"), Some("0 0 10 10".to_owned()));
+        }
+
+        {
+            let options = super::CompileOptions {
+                sigil: '@',
+                ..super::CompileOptions::default()
+            };
+            let template =
+                super::h_ns_with(super::Namespace::Svg, "<text @label>#@@1</text>", &options)
+                    .unwrap();
+            let refs = template.collect().unwrap();
+            let label = refs.get("label").unwrap();
+            assert_eq!(
+                label.unchecked_ref::<Element>().namespace_uri(),
+                Some(SVG.to_owned())
+            );
+            assert_eq!(label.text_content(), Some("#@1".to_owned()));
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn error_tests() {
+        use super::Stage0Error;
+
+        assert_eq!(super::h("").err(), Some(Stage0Error::EmptyTemplate));
+        assert_eq!(
+            super::h("<div #a><b #a></b></div>").err(),
+            Some(Stage0Error::DuplicateRef("a".to_owned()))
+        );
+        assert!(matches!(
+            super::h("<div #></div>"),
+            Err(Stage0Error::Parse(_))
+        ));
+        for source in [
+            "<div #a #b></div>",
+            "<div ##a></div>",
+            "<div #a.b></div>",
+            "<textarea>Hello #name</textarea>",
+        ]
+        .iter()
+        {
+            assert!(matches!(super::h(source), Err(Stage0Error::Parse(_))));
+        }
+
+        let err: JsValue = Stage0Error::MissingRef("foo".to_owned()).into();
+        let err = err.dyn_into::<js_sys::Error>().unwrap();
+        assert_eq!(String::from(err.message()), "missing ref `#foo`");
+    }
+
+    #[wasm_bindgen_test]
+    fn attr_binding_tests() {
+        let template =
+            super::h(r##"<a #link href="#href" class="#cls" data-nav="#/active">x</a>"##).unwrap();
+        let refs = template.collect().unwrap();
+        assert_eq!(refs.len(), 3);
+
+        let link = refs.get("link").unwrap().unchecked_ref::<Element>();
+        assert_eq!(link.get_attribute("data-nav"), Some("#/active".to_owned()));
+        assert_eq!(link.get_attribute("href"), Some("".to_owned()));
+
+        let mut instance = template.instantiate().unwrap();
+        let mut refs = instance.collect();
+        let cls = refs.remove("cls").unwrap().unchecked_into::<Attr>();
+        assert_eq!(cls.name(), "class");
+        cls.set_value("active");
+        let node: &Node = instance.as_ref();
+        assert_eq!(node.unchecked_ref::<Element>().class_name(), "active");
+
+        let template = super::h!("<a href='#href'><b class='#cls'></b></a>").unwrap();
+        let refs = template.collect().unwrap();
+        let cls = refs.get("cls").unwrap().unchecked_ref::<Attr>();
+        assert_eq!(cls.name(), "class");
+        assert_eq!(cls.value(), "");
+    }
+
+    #[wasm_bindgen_test]
+    fn anchor_tests() {
+        use super::Anchor;
+
+        let template = super::h("<ul><li>first</li><!-- #items --><li>last</li></ul>").unwrap();
+        let mut instance = template.instantiate().unwrap();
+        let items = instance.collect().remove("items").unwrap();
+        assert_eq!(items.node_type(), Node::COMMENT_NODE);
+
+        let anchor = Anchor::new(items).unwrap();
+        let document = web_sys::window().unwrap().document().unwrap();
+        anchor
+            .mount(&document.create_element("li").unwrap())
+            .unwrap();
+        anchor
+            .mount(&document.create_element("li").unwrap())
+            .unwrap();
+
+        let ul = anchor.parent().unwrap().unchecked_into::<Element>();
+        assert_eq!(
+            ul.inner_html(),
+            "<li>first</li><!--items--><li></li><li></li><!--/items--><li>last</li>"
+        );
+
+        anchor.clear().unwrap();
+        assert_eq!(
+            ul.inner_html(),
+            "<li>first</li><!--items--><!--/items--><li>last</li>"
+        );
+
+        let template = super::h!("<!--#rows--><p #footer></p>").unwrap();
+        let refs = template.collect().unwrap();
+        assert!(Anchor::new(refs.get("rows").unwrap().clone()).is_ok());
+        assert!(Anchor::new(refs.get("footer").unwrap().clone()).is_err());
+
+        assert!(super::h("<!--#alone-->").is_err());
+    }
+
+    #[wasm_bindgen_test]
+    fn collect_indexed_tests() {
+        let template =
+            super::h("<tr #row><td>#name</td><td class='#cls'>#count</td></tr>").unwrap();
+        assert_eq!(template.ref_index("row"), Some(0));
+        assert_eq!(template.ref_index("name"), Some(1));
+        assert_eq!(template.ref_index("cls"), Some(2));
+        assert_eq!(template.ref_index("count"), Some(3));
+        assert_eq!(template.ref_index("missing"), None);
+
+        let refs = template.collect_indexed().unwrap();
+        assert_eq!(refs.len(), 4);
+        let template_node: &Node = template.as_ref();
+        assert!(refs[0].is_same_node(Some(template_node)));
+
+        let count = template.ref_index("count").unwrap();
+        let (node, refs) = template.instantiate_indexed().unwrap();
+        assert!(refs[0].is_same_node(Some(&node)));
+        assert_eq!(refs[count].node_type(), Node::TEXT_NODE);
+        assert_eq!(refs[2].unchecked_ref::<Attr>().name(), "class");
+    }
+
+    #[wasm_bindgen_test]
+    fn text_interpolation_tests() {
+        {
+            let template = super::h("<span>Hello #name! You have #count items.</span>").unwrap();
+            let refs = template.collect().unwrap();
+            let node: &Node = template.as_ref();
+            let el = node.unchecked_ref::<Element>();
+
+            refs.get("name").unwrap().set_node_value(Some("Ada"));
+            refs.get("count").unwrap().set_node_value(Some("3"));
+            assert_eq!(
+                el.text_content(),
+                Some("Hello Ada! You have 3 items.".to_owned())
+            );
+
+            let name = refs.get("name").unwrap();
+            assert_eq!(
+                name.previous_sibling().unwrap().node_value(),
+                Some("Hello ".to_owned())
+            );
+        }
+
+        {
+            let template = super::h("#a and #b").unwrap();
+            let refs = template.collect().unwrap();
+            assert_eq!(refs.len(), 2);
+            let node: &Node = template.as_ref();
+            assert_eq!(node.text_content(), Some(" and ".to_owned()));
+        }
+
+        {
+            let template = super::h!("<p>#count item#plural left</p>").unwrap();
+            let mut instance = template.instantiate().unwrap();
+            let refs = instance.collect();
+            refs.get("count").unwrap().set_node_value(Some("2"));
+            refs.get("plural").unwrap().set_node_value(Some("s"));
+            let node: &Node = instance.as_ref();
+            assert_eq!(node.text_content(), Some("2 items left".to_owned()));
+        }
+
+        {
+            let template = super::h("<p>Price # 5</p>").unwrap();
+            assert!(template.collect().unwrap().is_empty());
+        }
+
+        {
+            let template = super::h("<style>.a{color:#fff}.b{color:#000}</style>").unwrap();
+            assert!(template.collect().unwrap().is_empty());
+            let node: &Node = template.as_ref();
+            assert_eq!(
+                node.text_content(),
+                Some(".a{color:#fff}.b{color:#000}".to_owned())
+            );
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn escape_tests() {
+        use super::CompileOptions;
+
+        {
+            let template = super::h("<p title='##tag'>##1 priority, ###n</p>").unwrap();
+            let refs = template.collect().unwrap();
+            assert_eq!(refs.len(), 1);
+            refs.get("n").unwrap().set_node_value(Some("2"));
+            let node: &Node = template.as_ref();
+            let el = node.unchecked_ref::<Element>();
+            assert_eq!(el.get_attribute("title"), Some("#tag".to_owned()));
+            assert_eq!(el.text_content(), Some("#1 priority, #2".to_owned()));
+        }
+
+        {
+            let template = super::h!("<p title='##tag'>##1 priority, ###n</p>").unwrap();
+            let node: &Node = template.as_ref();
+            assert_eq!(node.text_content(), Some("#1 priority, #".to_owned()));
+        }
+
+        {
+            let options = CompileOptions {
+                sigil: '@',
+                ..CompileOptions::default()
+            };
+            let template =
+                super::h_with("<a @link href='#/active'>@@x #y @z</a>", &options).unwrap();
+            let refs = template.collect().unwrap();
+            assert_eq!(refs.len(), 2);
+            assert!(refs.contains_key("link"));
+            let node: &Node = template.as_ref();
+            let el = node.unchecked_ref::<Element>();
+            assert_eq!(el.get_attribute("href"), Some("#/active".to_owned()));
+            assert_eq!(el.text_content(), Some("@x #y ".to_owned()));
+        }
+
+        {
+            let template =
+                super::h!(sigil = '@', "<a @link href='#/active'>@@x #y @z</a>").unwrap();
+            let refs = template.collect().unwrap();
+            assert_eq!(refs.len(), 2);
+            assert!(refs.contains_key("z"));
+            let node: &Node = template.as_ref();
+            assert_eq!(node.text_content(), Some("@x #y ".to_owned()));
+        }
+
+        assert!(super::h_with(
+            "<p></p>",
+            &CompileOptions {
+                sigil: 'a',
+                ..CompileOptions::default()
+            }
+        )
+        .is_err());
+    }
+
+    #[wasm_bindgen_test]
+    fn collapse_whitespace_tests() {
+        use super::CompileOptions;
+
+        let options = CompileOptions {
+            collapse_whitespace: true,
+            ..CompileOptions::default()
+        };
+
+        {
+            let template = super::h_with(
+                "<div>
+                    <h1>#count</h1>
+                    <p>  Hello <b>big</b>
+                        <i>world</i>  </p>
+                    <pre> keep\n  this </pre>
+                </div>",
+                &options,
+            )
+            .unwrap();
+            let node: &Node = template.as_ref();
+            let el = node.unchecked_ref::<Element>();
+            assert_eq!(
+                el.inner_html(),
+                "<h1></h1><p>Hello <b>big</b> <i>world</i></p><pre> keep\n  this </pre>"
+            );
+            let refs = template.collect().unwrap();
+            assert_eq!(refs.get("count").unwrap().parent_node(), node.first_child());
+        }
+
+        {
+            let template = super::h_with("<p>&nbsp;<b>a</b>&nbsp; </p>", &options).unwrap();
+            let node: &Node = template.as_ref();
+            assert_eq!(node.text_content(), Some("\u{a0}a\u{a0}".to_owned()));
+        }
+
+        {
+            let template = super::h!(
+                collapse_whitespace = true,
+                "<ul>
+                    <li>#first</li>
+                    <li> a  b </li>
+                </ul>"
+            )
+            .unwrap();
+            let node: &Node = template.as_ref();
+            assert_eq!(
+                node.unchecked_ref::<Element>().inner_html(),
+                "<li></li><li>a b</li>"
+            );
+        }
+
+        {
+            let template = super::h("<div> <p></p> </div>").unwrap();
+            let node: &Node = template.as_ref();
+            assert_eq!(node.unchecked_ref::<Element>().inner_html(), " <p></p> ");
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn hydrate_tests() {
+        use super::{ssr, Stage0Error};
+
+        let document = web_sys::window().unwrap().document().unwrap();
+        let container = document.create_element("div").unwrap();
+
+        {
+            let source = "<p #el class='#cls'>Hello #name! <!--#items--></p>";
+            let template = super::h(source).unwrap();
+            let html =
+                ssr::render(source, vec![("cls", "big"), ("items", "<b>1</b><b>2</b>")]).unwrap();
+            container.set_inner_html(&html);
+            let live = container.first_child().unwrap();
+
+            let refs = template.hydrate(&live).unwrap();
+            assert_eq!(refs.len(), 4);
+            assert_eq!(refs.get("el"), Some(&live));
+            let cls = refs.get("cls").unwrap().unchecked_ref::<Attr>();
+            assert_eq!(cls.value(), "big");
+
+            refs.get("name").unwrap().set_node_value(Some("Ada"));
+            assert_eq!(live.text_content(), Some("Hello Ada! 12".to_owned()));
+
+            let items = super::Anchor::new(refs.get("items").unwrap().clone()).unwrap();
+            items.clear().unwrap();
+            assert_eq!(live.text_content(), Some("Hello Ada! ".to_owned()));
+        }
+
+        {
+            let template = super::h("#a and #b").unwrap();
+            container.set_inner_html(&ssr::render("#a and #b", vec![("b", "2")]).unwrap());
+            let refs = template.hydrate(&container).unwrap();
+            refs.get("a").unwrap().set_node_value(Some("1"));
+            assert_eq!(container.inner_html(), "1 and 2");
+        }
+
+        {
+            let template = super::h("<ul><li #first></li></ul>").unwrap();
+            container.set_inner_html("<ul><p></p></ul>");
+            let live = container.first_child().unwrap();
+            assert_eq!(
+                template.hydrate(&live),
+                Err(Stage0Error::HydrationMismatch {
+                    path: vec![0],
+                    expected: "<li>".to_owned(),
+                    found: "<p>".to_owned(),
+                })
+            );
+
+            container.set_inner_html("<ul><li></li><li></li></ul>");
+            let live = container.first_child().unwrap();
+            assert!(matches!(
+                template.hydrate(&live),
+                Err(Stage0Error::HydrationMismatch { path, .. }) if path == vec![1]
+            ));
+        }
+    }
 }
@@ -1,351 +1,1989 @@
-use std::collections::HashMap;
+use super::dom::{Dom, NodeKind};
+use super::Stage0Error;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
+use std::ops::Range;
 use web_sys::Node;
 
-#[allow(clippy::cyclomatic_complexity, clippy::too_many_arguments)]
-pub fn reconcile<Item, CreateNode, UpdateNode>(
-    parent: &Node,
-    rendered_ids: &[Id],
+// The nodes currently rendered between `before_node` and `after_node`, one per key.
+fn rendered_nodes<N: Dom>(
+    parent: &N,
+    count: usize,
+    before_node: Option<&N>,
+) -> Result<Vec<N>, Stage0Error> {
+    let mut nodes = Vec::with_capacity(count);
+    let mut node = match before_node {
+        Some(before_node) => before_node.next_sibling(),
+        None => parent.first_child(),
+    };
+    while nodes.len() < count {
+        let current = node.ok_or(Stage0Error::RenderedCount {
+            expected: count,
+            found: nodes.len(),
+        })?;
+        node = current.next_sibling();
+        nodes.push(current);
+    }
+    Ok(nodes)
+}
+
+/// The fast path `reconcile` took to reach the new list.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReconcilePath {
+    /// There were no new items, so everything was removed.
+    Clear,
+    /// Nothing was rendered, so every item was created.
+    Create,
+    /// The common prefix, suffix and swaps settled every item.
+    Ends,
+    /// Only items removed from the middle were left.
+    Shrink,
+    /// Only items added to the middle were left.
+    Add,
+    /// None of the remaining nodes could be reused, so they were all replaced.
+    Replace,
+    /// The remaining nodes were reused, moving those off the longest increasing
+    /// subsequence of their old positions.
+    Lis,
+}
+
+/// A report of the DOM operations `reconcile` performed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReconcileStats {
+    pub created: usize,
+    pub updated: usize,
+    pub moved: usize,
+    pub removed: usize,
+    pub path: ReconcilePath,
+}
+
+/// A single DOM operation performed by `reconcile`, passed to the observer of
+/// `reconcile_observed`.
+#[derive(Debug)]
+pub enum Operation<'a, N> {
+    /// `node` was created and inserted.
+    Create(&'a N),
+    /// `node` was passed to `update_node`.
+    Update(&'a N),
+    /// `node` was moved to a new position.
+    Move(&'a N),
+    /// `node` was removed.
+    Remove(&'a N),
+}
+
+/// The run of sibling nodes an item rendered to, from `first` to `last`, for lists
+/// where items aren't a single node. See `reconcile_ranges`.
+#[derive(Clone, Debug)]
+pub struct NodeRange<N = Node> {
+    first: N,
+    last: N,
+    // The fragment the nodes came in, so they can be inserted in one go. Holding it also
+    // keeps their siblings reachable, as a node doesn't necessarily keep its parent alive.
+    fragment: Option<N>,
+}
+
+impl<N: Dom> NodeRange<N> {
+    /// The children of `node` if it's a fragment, which must have some, or just `node`.
+    pub fn new(node: N) -> Result<NodeRange<N>, Stage0Error> {
+        if node.kind() != NodeKind::Fragment {
+            return Ok(NodeRange::from_node(node));
+        }
+        match (node.first_child(), node.last_child()) {
+            (Some(first), Some(last)) => Ok(NodeRange {
+                first,
+                last,
+                fragment: Some(node),
+            }),
+            _ => Err(Stage0Error::EmptyFragment),
+        }
+    }
+
+    pub fn first(&self) -> &N {
+        &self.first
+    }
+
+    pub fn last(&self) -> &N {
+        &self.last
+    }
+
+    /// The nodes from `first` to `last`, in order.
+    pub fn nodes(&self) -> Vec<N> {
+        let mut nodes = Vec::new();
+        let mut node = Some(self.first.clone());
+        while let Some(current) = node {
+            let done = current.is_same_node(&self.last);
+            node = current.next_sibling();
+            nodes.push(current);
+            if done {
+                break;
+            }
+        }
+        nodes
+    }
+}
+
+// What an item renders to: a single node, or a `NodeRange` that's inserted, moved and
+// removed as one.
+pub(crate) trait Unit<N>: Clone {
+    // The node to insert the items before this one in front of.
+    fn first_node(&self) -> &N;
+
+    fn insert_into(&self, parent: &N, before: Option<&N>) -> Result<(), Stage0Error>;
+
+    fn remove_from(&self, parent: &N) -> Result<(), Stage0Error>;
+
+    // Wraps a node found by walking the siblings of the list.
+    fn from_node(node: N) -> Self;
+}
+
+impl<N: Dom> Unit<N> for N {
+    fn first_node(&self) -> &N {
+        self
+    }
+
+    fn insert_into(&self, parent: &N, before: Option<&N>) -> Result<(), Stage0Error> {
+        parent.insert_before(self, before)
+    }
+
+    fn remove_from(&self, parent: &N) -> Result<(), Stage0Error> {
+        parent.remove_child(self)
+    }
+
+    fn from_node(node: N) -> N {
+        node
+    }
+}
+
+impl<N: Dom> Unit<N> for NodeRange<N> {
+    fn first_node(&self) -> &N {
+        &self.first
+    }
+
+    fn insert_into(&self, parent: &N, before: Option<&N>) -> Result<(), Stage0Error> {
+        // Like a node inserted before itself, the range is already in place.
+        if before.is_some_and(|before| before.is_same_node(&self.first)) {
+            return Ok(());
+        }
+        // A new range is inserted along with its fragment in one go.
+        if let Some(fragment) = &self.fragment {
+            if self
+                .first
+                .parent_node()
+                .is_some_and(|node| node.is_same_node(fragment))
+            {
+                return parent.insert_before(fragment, before);
+            }
+        }
+        for node in self.nodes() {
+            parent.insert_before(&node, before)?;
+        }
+        Ok(())
+    }
+
+    fn remove_from(&self, parent: &N) -> Result<(), Stage0Error> {
+        for node in self.nodes() {
+            parent.remove_child(&node)?;
+        }
+        Ok(())
+    }
+
+    fn from_node(node: N) -> NodeRange<N> {
+        NodeRange {
+            first: node.clone(),
+            last: node,
+            fragment: None,
+        }
+    }
+}
+
+// Counts operations into the stats and forwards them to the observer, and removes
+// nodes with `on_remove`.
+struct Recorder<Observer, Remove> {
+    stats: ReconcileStats,
+    observer: Observer,
+    on_remove: Remove,
+}
+
+impl<Observer, Remove> Recorder<Observer, Remove> {
+    fn new(observer: Observer, on_remove: Remove) -> Recorder<Observer, Remove> {
+        Recorder {
+            stats: ReconcileStats {
+                created: 0,
+                updated: 0,
+                moved: 0,
+                removed: 0,
+                path: ReconcilePath::Ends,
+            },
+            observer,
+            on_remove,
+        }
+    }
+
+    fn record<N>(&mut self, op: Operation<'_, N>)
+    where
+        Observer: FnMut(Operation<'_, N>),
+    {
+        match op {
+            Operation::Create(_) => self.stats.created += 1,
+            Operation::Update(_) => self.stats.updated += 1,
+            Operation::Move(_) => self.stats.moved += 1,
+            Operation::Remove(_) => self.stats.removed += 1,
+        }
+        (self.observer)(op);
+    }
+
+    fn remove<N, U>(&mut self, parent: &N, node: &U) -> Result<(), Stage0Error>
+    where
+        Observer: FnMut(Operation<'_, U>),
+        Remove: FnMut(&N, &U) -> Result<(), Stage0Error>,
+    {
+        (self.on_remove)(parent, node)?;
+        self.record(Operation::Remove(node));
+        Ok(())
+    }
+}
+
+// Removes everything between `before_node` and `after_node`.
+fn clear<N, U, Observer, Remove>(
+    parent: &N,
+    before_node: Option<&N>,
+    after_node: Option<&N>,
+    recorder: &mut Recorder<Observer, Remove>,
+) -> Result<(), Stage0Error>
+where
+    N: Dom,
+    U: Unit<N>,
+    Observer: FnMut(Operation<'_, U>),
+    Remove: FnMut(&N, &U) -> Result<(), Stage0Error>,
+{
+    let mut node = match before_node {
+        Some(before_node) => before_node.next_sibling(),
+        None => parent.first_child(),
+    };
+    while let Some(current) = node {
+        if after_node.is_some_and(|after_node| current.is_same_node(after_node)) {
+            break;
+        }
+        node = current.next_sibling();
+        recorder.remove(parent, &U::from_node(current))?;
+    }
+    Ok(())
+}
+
+// How to find the nodes rendered for `rendered_ids`: by walking the siblings after a
+// `before_node`, or from an array the caller kept.
+enum Rendered<'a, N, U> {
+    After(Option<N>),
+    Nodes(&'a [U]),
+}
+
+// A repeated key would have two items share one node, so it's rejected before anything
+// is touched.
+fn check_keys<Key: Eq + Hash>(new_ids: &[Key]) -> Result<(), Stage0Error> {
+    let mut seen = HashSet::with_capacity(new_ids.len());
+    match new_ids.iter().position(|id| !seen.insert(id)) {
+        Some(i) => Err(Stage0Error::DuplicateKey(i)),
+        None => Ok(()),
+    }
+}
+
+// What's left after the fast paths: the unsettled ranges of old nodes and new items,
+// and the node the middle of the list is rendered before.
+struct Middle<'a, N, U: Clone, Key> {
+    nodes: Cow<'a, [U]>,
+    new_ids: Vec<Key>,
+    prev: Range<usize>,
+    new: Range<usize>,
+    tail: Option<N>,
+}
+
+// Runs the fast paths shared by every keyed reconcile: clear, create, common prefix and
+// suffix, swaps, and pure shrinking or adding once the ends are settled. Returns `None`
+// if nothing is left to do.
+#[allow(clippy::too_many_arguments)]
+fn reconcile_ends<'a, N, U, Item, Key, GetKey, CreateNode, UpdateNode, Observer, Remove>(
+    parent: &N,
+    rendered_ids: &[Key],
+    rendered: Rendered<'a, N, U>,
     new_items: &mut [Item],
-    create_node: CreateNode,
-    update_node: UpdateNode,
-    before_node: Option<Node>,
-    mut after_node: Option<Node>,
-) where
-    CreateNode: Fn(&mut Item) -> Node,
-    UpdateNode: Fn(&Node, &mut Item),
-    GetItemId: Fn(&Item) -> Id,
-    Id: PartialEq + Eq + Hash,
+    get_key: GetKey,
+    create_node: &mut CreateNode,
+    update_node: &mut UpdateNode,
+    after_node: Option<N>,
+    recorder: &mut Recorder<Observer, Remove>,
+) -> Result<Option<Middle<'a, N, U, Key>>, Stage0Error>
+where
+    N: Dom,
+    U: Unit<N>,
+    GetKey: Fn(&Item) -> Key,
+    CreateNode: FnMut(&mut Item) -> U,
+    UpdateNode: FnMut(&U, &mut Item),
+    Observer: FnMut(Operation<'_, U>),
+    Remove: FnMut(&N, &U) -> Result<(), Stage0Error>,
+    Key: Eq + Hash,
 {
+    let new_ids: Vec<Key> = new_items.iter().map(&get_key).collect();
+    check_keys(&new_ids)?;
+
     // Fast path for clear
     if new_items.is_empty() {
-        if before_node.is_some() || after_node.is_some() {
-            let mut node = if let Some(before_node) = before_node {
-                before_node.next_sibling()
-            } else {
-                parent.first_child()
-            };
-
-            while let Some(n) = node {
-                if n.is_same_node(after_node.as_ref()) {
-                    break;
+        recorder.stats.path = ReconcilePath::Clear;
+        match rendered {
+            Rendered::After(before_node) => {
+                clear(parent, before_node.as_ref(), after_node.as_ref(), recorder)?
+            }
+            Rendered::Nodes(nodes) => {
+                for node in nodes.iter() {
+                    recorder.remove(parent, node)?;
                 }
-                let tmp = n.next_sibling();
-                parent.remove_child(&n).unwrap();
-                node = tmp;
             }
-        } else {
-            parent.set_text_content(None);
         }
-        return;
+        return Ok(None);
     }
 
     // Fast path for create
     if rendered_ids.is_empty() {
-        for item in new_items {
+        recorder.stats.path = ReconcilePath::Create;
+        for item in new_items.iter_mut() {
             let node = create_node(item);
-            match &after_node {
-                Some(after_node) => {
-                    parent.insert_before(&node, Some(after_node)).unwrap();
-                }
-                None => {
-                    parent.append_child(&node).unwrap();
-                }
-            }
+            node.insert_into(parent, after_node.as_ref())?;
+            recorder.record(Operation::Create(&node));
         }
-        return;
+        return Ok(None);
     }
 
-    let mut prev_start = 0;
-    let mut new_start = 0;
-    let mut should_loop = true;
-    let mut prev_end = rendered_ids.len() - 1;
-    let mut new_end = new_items.len() - 1;
-    let mut prev_start_node = if let Some(before_node) = &before_node {
-        before_node.next_sibling()
-    } else {
-        parent.first_child()
-    };
-    let mut new_start_node = prev_start_node.clone();
-    let mut prev_end_node = if let Some(after_node) = &after_node {
-        after_node.previous_sibling()
-    } else {
-        parent.last_child()
+    let nodes = match rendered {
+        Rendered::After(before_node) => Cow::Owned(
+            rendered_nodes(parent, rendered_ids.len(), before_node.as_ref())?
+                .into_iter()
+                .map(U::from_node)
+                .collect(),
+        ),
+        Rendered::Nodes(nodes) => Cow::Borrowed(nodes),
     };
-    let mut new_end_node = prev_end_node.clone();
 
-    'fixes: while should_loop {
+    // The unsettled ranges, with exclusive ends, and the node the middle of the list is
+    // rendered before.
+    let (mut prev_start, mut prev_end) = (0, rendered_ids.len());
+    let (mut new_start, mut new_end) = (0, new_items.len());
+    let mut tail = after_node;
+
+    let mut should_loop = true;
+    while should_loop && prev_start < prev_end && new_start < new_end {
         should_loop = false;
 
         // Skip prefix
-        let mut a = &rendered_ids[prev_start];
-        let mut b = &mut new_items[new_start];
-        while a == &get_item_id(b) {
-            if let Some(p) = prev_start_node {
-                update_node(&p, b);
-                new_start_node = p.next_sibling();
-                prev_start_node = new_start_node.clone();
-            }
+        while prev_start < prev_end
+            && new_start < new_end
+            && rendered_ids[prev_start] == new_ids[new_start]
+        {
+            update_node(&nodes[prev_start], &mut new_items[new_start]);
+            recorder.record(Operation::Update(&nodes[prev_start]));
             prev_start += 1;
             new_start += 1;
-            if prev_end < prev_start || new_end < new_start {
-                break 'fixes;
-            }
-            a = &rendered_ids[prev_start];
-            b = &mut new_items[new_start];
         }
 
         // Skip suffix
-        a = &rendered_ids[prev_end];
-        b = &mut new_items[new_end];
-        while a == &get_item_id(b) {
-            after_node = prev_end_node.clone();
-            if let Some(p) = &prev_end_node {
-                update_node(p, b);
-                new_end_node = p.previous_sibling();
-                prev_end_node = new_end_node.clone();
-            }
+        while prev_start < prev_end
+            && new_start < new_end
+            && rendered_ids[prev_end - 1] == new_ids[new_end - 1]
+        {
+            update_node(&nodes[prev_end - 1], &mut new_items[new_end - 1]);
+            recorder.record(Operation::Update(&nodes[prev_end - 1]));
+            tail = Some(nodes[prev_end - 1].first_node().clone());
             prev_end -= 1;
             new_end -= 1;
-            if prev_end < prev_start || new_end < new_start {
-                break 'fixes;
-            }
-            a = &rendered_ids[prev_end];
-            b = &mut new_items[new_end];
         }
 
         // Fast path to swap backward
-        a = &rendered_ids[prev_end];
-        b = &mut new_items[new_start];
-        while a == &get_item_id(b) {
+        while prev_start < prev_end
+            && new_start < new_end
+            && rendered_ids[prev_end - 1] == new_ids[new_start]
+        {
             should_loop = true;
-            if let Some(p) = &prev_end_node {
-                update_node(p, b);
-                let _node = p.previous_sibling();
-                parent.insert_before(p, new_start_node.as_ref()).unwrap();
-                new_end_node = _node;
-                prev_end_node = new_end_node.clone();
-            }
-            new_start += 1;
+            let node = &nodes[prev_end - 1];
+            update_node(node, &mut new_items[new_start]);
+            recorder.record(Operation::Update(node));
+            node.insert_into(parent, Some(nodes[prev_start].first_node()))?;
+            recorder.record(Operation::Move(node));
             prev_end -= 1;
-            if prev_end < prev_start || new_end < new_start {
-                break 'fixes;
-            }
-            a = &rendered_ids[prev_end];
-            b = &mut new_items[new_start];
+            new_start += 1;
         }
 
         // Fast path to swap forward
-        a = &rendered_ids[prev_start];
-        b = &mut new_items[new_end];
-        while a == &get_item_id(b) {
+        while prev_start < prev_end
+            && new_start < new_end
+            && rendered_ids[prev_start] == new_ids[new_end - 1]
+        {
             should_loop = true;
-            if let Some(p) = &prev_start_node {
-                update_node(p, b);
-                let _node = p.next_sibling();
-                parent.insert_before(p, after_node.as_ref()).unwrap();
-                after_node = prev_start_node.clone();
-                new_end_node = prev_start_node.clone();
-                prev_start_node = _node;
-            }
+            let node = &nodes[prev_start];
+            update_node(node, &mut new_items[new_end - 1]);
+            recorder.record(Operation::Update(node));
+            node.insert_into(parent, tail.as_ref())?;
+            recorder.record(Operation::Move(node));
+            tail = Some(node.first_node().clone());
+            prev_start += 1;
             new_end -= 1;
-            if prev_end < prev_start || new_end < new_start {
-                break 'fixes;
-            }
-            a = &rendered_ids[prev_start];
-            b = &mut new_items[new_end];
         }
     }
 
     // Fast path for shrink
-    if new_end < new_start {
-        if prev_start <= prev_end {
-            while prev_start <= prev_end {
-                if let Some(p) = &prev_end_node {
-                    if prev_end == 0 {
-                        parent.remove_child(p).unwrap();
-                    } else {
-                        let next = p.previous_sibling();
-                        parent.remove_child(p).unwrap();
-                        prev_end_node = next;
-                    }
-                }
-                prev_end -= 1;
-            }
+    if new_end == new_start {
+        if prev_end > prev_start {
+            recorder.stats.path = ReconcilePath::Shrink;
+        }
+        for node in nodes[prev_start..prev_end].iter() {
+            recorder.remove(parent, node)?;
         }
-        return;
+        return Ok(None);
     }
 
     // Fast path for add
-    if prev_end < prev_start {
-        if new_start <= new_end {
-            while new_start <= new_end {
-                let node = create_node(&mut new_items[new_start]);
-                if after_node.is_some() {
-                    parent.insert_before(&node, after_node.as_ref()).unwrap();
+    if prev_end == prev_start {
+        recorder.stats.path = ReconcilePath::Add;
+        for item in new_items[new_start..new_end].iter_mut() {
+            let node = create_node(item);
+            node.insert_into(parent, tail.as_ref())?;
+            recorder.record(Operation::Create(&node));
+        }
+        return Ok(None);
+    }
+
+    Ok(Some(Middle {
+        nodes,
+        new_ids,
+        prev: prev_start..prev_end,
+        new: new_start..new_end,
+        tail,
+    }))
+}
+
+// The full keyed reconcile: the fast paths, then reusing the remaining nodes with as
+// few moves as possible.
+#[allow(clippy::too_many_arguments)]
+fn reconcile_keyed<N, U, Item, Key, GetKey, CreateNode, UpdateNode, Observer, Remove>(
+    parent: &N,
+    rendered_ids: &[Key],
+    rendered: Rendered<N, U>,
+    new_items: &mut [Item],
+    get_key: GetKey,
+    mut create_node: CreateNode,
+    mut update_node: UpdateNode,
+    after_node: Option<N>,
+    recorder: &mut Recorder<Observer, Remove>,
+) -> Result<(), Stage0Error>
+where
+    N: Dom,
+    U: Unit<N>,
+    GetKey: Fn(&Item) -> Key,
+    CreateNode: FnMut(&mut Item) -> U,
+    UpdateNode: FnMut(&U, &mut Item),
+    Observer: FnMut(Operation<'_, U>),
+    Remove: FnMut(&N, &U) -> Result<(), Stage0Error>,
+    Key: Eq + Hash,
+{
+    let middle = reconcile_ends(
+        parent,
+        rendered_ids,
+        rendered,
+        new_items,
+        get_key,
+        &mut create_node,
+        &mut update_node,
+        after_node,
+        recorder,
+    )?;
+    let Middle {
+        nodes,
+        new_ids,
+        prev,
+        new,
+        mut tail,
+    } = match middle {
+        Some(middle) => middle,
+        None => return Ok(()),
+    };
+
+    // Positions for reusing nodes from current DOM state
+    let indices: HashMap<&Key, usize> = new.clone().map(|i| (&new_ids[i], i)).collect();
+    let mut positions: Vec<Option<usize>> = vec![None; new.len()];
+    for i in prev {
+        match indices.get(&rendered_ids[i]) {
+            Some(&new_idx) => positions[new_idx - new.start] = Some(i),
+            None => {
+                recorder.remove(parent, &nodes[i])?;
+            }
+        }
+    }
+    recorder.stats.path = if positions.iter().all(Option::is_none) {
+        ReconcilePath::Replace
+    } else {
+        ReconcilePath::Lis
+    };
+
+    // Nodes on the longest increasing run of old positions stay where they are, and
+    // everything else is moved or created around them, back to front.
+    let mut stable = longest_increasing_subsequence(&positions)
+        .into_iter()
+        .rev()
+        .peekable();
+    for i in new.clone().rev() {
+        let offset = i - new.start;
+        match positions[offset] {
+            Some(pos) => {
+                let node = &nodes[pos];
+                update_node(node, &mut new_items[i]);
+                recorder.record(Operation::Update(node));
+                if stable.peek() == Some(&offset) {
+                    stable.next();
                 } else {
-                    parent.append_child(&node).unwrap();
+                    node.insert_into(parent, tail.as_ref())?;
+                    recorder.record(Operation::Move(node));
                 }
-                new_start += 1;
+                tail = Some(node.first_node().clone());
+            }
+            None => {
+                let node = create_node(&mut new_items[i]);
+                node.insert_into(parent, tail.as_ref())?;
+                recorder.record(Operation::Create(&node));
+                tail = Some(node.first_node().clone());
             }
         }
-        return;
     }
+    Ok(())
+}
 
-    // Positions for reusing nodes from current DOM state
-    let mut positions: Vec<isize> = vec![-1; new_end + 1 - new_start];
+/// Updates the children of `parent` rendered for the items keyed `rendered_ids` to show
+/// `new_items` instead, reusing, moving and updating the nodes of items whose key is
+/// unchanged and creating or removing the rest. Returns what it did to get there, or
+/// `DuplicateKey` without touching the DOM if two of `new_items` share a key.
+///
+/// Only the nodes between `before_node` and `after_node` are touched, so a list can
+/// share its parent with static siblings. Store the keys of `new_items` to pass as
+/// `rendered_ids` next time.
+///
+/// Removed nodes are detached right away, since the next call takes every node after
+/// `before_node` for a rendered one and would trip over any still leaving. To keep them
+/// around for an exit animation, track the nodes with `reconcile_nodes_deferred` or a
+/// `KeyedList` instead.
+#[allow(clippy::too_many_arguments)]
+pub fn reconcile<N, Item, Key, GetKey, CreateNode, UpdateNode>(
+    parent: &N,
+    rendered_ids: &[Key],
+    new_items: &mut [Item],
+    get_key: GetKey,
+    create_node: CreateNode,
+    update_node: UpdateNode,
+    before_node: Option<N>,
+    after_node: Option<N>,
+) -> Result<ReconcileStats, Stage0Error>
+where
+    N: Dom,
+    GetKey: Fn(&Item) -> Key,
+    CreateNode: FnMut(&mut Item) -> N,
+    UpdateNode: FnMut(&N, &mut Item),
+    Key: Eq + Hash,
+{
+    reconcile_observed(
+        parent,
+        rendered_ids,
+        new_items,
+        get_key,
+        create_node,
+        update_node,
+        before_node,
+        after_node,
+        |_| {},
+    )
+}
+
+/// Like `reconcile`, but also passes each DOM operation to `observer` as it happens.
+#[allow(clippy::too_many_arguments)]
+pub fn reconcile_observed<N, Item, Key, GetKey, CreateNode, UpdateNode, Observer>(
+    parent: &N,
+    rendered_ids: &[Key],
+    new_items: &mut [Item],
+    get_key: GetKey,
+    create_node: CreateNode,
+    update_node: UpdateNode,
+    before_node: Option<N>,
+    after_node: Option<N>,
+    observer: Observer,
+) -> Result<ReconcileStats, Stage0Error>
+where
+    N: Dom,
+    GetKey: Fn(&Item) -> Key,
+    CreateNode: FnMut(&mut Item) -> N,
+    UpdateNode: FnMut(&N, &mut Item),
+    Observer: FnMut(Operation<'_, N>),
+    Key: Eq + Hash,
+{
+    let mut recorder = Recorder::new(observer, |parent: &N, node: &N| parent.remove_child(node));
+    reconcile_keyed(
+        parent,
+        rendered_ids,
+        Rendered::After(before_node),
+        new_items,
+        get_key,
+        create_node,
+        update_node,
+        after_node,
+        &mut recorder,
+    )?;
+    Ok(recorder.stats)
+}
+
+// The first pass of `try_reconcile` and friends: updates the nodes of kept keys and
+// creates nodes for the rest, without touching the DOM. Returns the new keys and the
+// node of every new item, for `place`.
+pub(crate) fn prepare<N, Item, Key, GetKey, CreateNode, UpdateNode, E>(
+    rendered_ids: &[Key],
+    nodes: &[N],
+    new_items: &mut [Item],
+    get_key: GetKey,
+    create_node: &mut CreateNode,
+    update_node: &mut UpdateNode,
+) -> Result<(Vec<Key>, Vec<N>), E>
+where
+    N: Clone,
+    GetKey: Fn(&Item) -> Key,
+    CreateNode: FnMut(&mut Item) -> Result<N, E>,
+    UpdateNode: FnMut(&N, &mut Item) -> Result<(), E>,
+    Key: Eq + Hash,
+    E: From<Stage0Error>,
+{
+    let new_ids: Vec<Key> = new_items.iter().map(&get_key).collect();
+    let old: HashMap<&Key, usize> = rendered_ids
+        .iter()
+        .enumerate()
+        .map(|(i, id)| (id, i))
+        .collect();
+    check_keys(&new_ids)?;
+    let mut prepared = Vec::with_capacity(new_ids.len());
+    for (id, item) in new_ids.iter().zip(new_items.iter_mut()) {
+        prepared.push(match old.get(id) {
+            Some(&pos) => {
+                update_node(&nodes[pos], item)?;
+                nodes[pos].clone()
+            }
+            None => create_node(item)?,
+        });
+    }
+    Ok((new_ids, prepared))
+}
+
+// The second pass: rearranges the DOM from `nodes`, rendered for `rendered_ids`, to the
+// `prepared` nodes of `new_ids`, by reconciling item indices in their place.
+pub(crate) fn place<N, U, Key, Remove>(
+    parent: &N,
+    rendered_ids: &[Key],
+    nodes: &[U],
+    new_ids: &[Key],
+    prepared: &[U],
+    after_node: Option<N>,
+    on_remove: Remove,
+) -> Result<ReconcileStats, Stage0Error>
+where
+    N: Dom,
+    U: Unit<N>,
+    Remove: FnMut(&N, &U) -> Result<(), Stage0Error>,
+    Key: Eq + Hash,
+{
+    let rendered_ids: Vec<&Key> = rendered_ids.iter().collect();
+    let mut indices: Vec<usize> = (0..new_ids.len()).collect();
+    let mut recorder = Recorder::new(|_: Operation<'_, U>| {}, on_remove);
+    reconcile_keyed(
+        parent,
+        &rendered_ids,
+        Rendered::Nodes(nodes),
+        &mut indices,
+        |i| &new_ids[*i],
+        |i| prepared[*i].clone(),
+        |_, _| {},
+        after_node,
+        &mut recorder,
+    )?;
+    Ok(recorder.stats)
+}
+
+// Hands `node` to `on_remove` along with a `done` callback that detaches it from `parent`.
+pub(crate) fn remove_later<N, OnRemove>(parent: &N, node: &N, on_remove: &mut OnRemove)
+where
+    N: Dom + 'static,
+    OnRemove: FnMut(&N, Box<dyn FnOnce()>),
+{
+    let (parent, leaving) = (parent.clone(), node.clone());
+    on_remove(
+        node,
+        Box::new(move || {
+            // The list may have been cleared in the meantime.
+            if leaving
+                .parent_node()
+                .is_some_and(|node| node.is_same_node(&parent))
+            {
+                let _ = parent.remove_child(&leaving);
+            }
+        }),
+    );
+}
+
+/// Like `reconcile`, but `create_node` and `update_node` can fail. They're all called
+/// before the DOM is changed, so on the first error nothing has been inserted, moved or
+/// removed yet and `rendered_ids` still describes the list. Nodes already updated keep
+/// their changes. Keys must be unique.
+#[allow(clippy::too_many_arguments)]
+pub fn try_reconcile<N, Item, Key, GetKey, CreateNode, UpdateNode, E>(
+    parent: &N,
+    rendered_ids: &[Key],
+    new_items: &mut [Item],
+    get_key: GetKey,
+    mut create_node: CreateNode,
+    mut update_node: UpdateNode,
+    before_node: Option<N>,
+    after_node: Option<N>,
+) -> Result<ReconcileStats, E>
+where
+    N: Dom,
+    GetKey: Fn(&Item) -> Key,
+    CreateNode: FnMut(&mut Item) -> Result<N, E>,
+    UpdateNode: FnMut(&N, &mut Item) -> Result<(), E>,
+    Key: Eq + Hash,
+    E: From<Stage0Error>,
+{
+    let nodes = rendered_nodes(parent, rendered_ids.len(), before_node.as_ref())?;
+    let (new_ids, prepared) = prepare(
+        rendered_ids,
+        &nodes,
+        new_items,
+        get_key,
+        &mut create_node,
+        &mut update_node,
+    )?;
+    Ok(place(
+        parent,
+        rendered_ids,
+        &nodes,
+        &new_ids,
+        &prepared,
+        after_node,
+        |parent: &N, node: &N| parent.remove_child(node),
+    )?)
+}
+
+/// Like `reconcile`, but with the rendered nodes kept in `nodes`, in the same order as
+/// `rendered_ids`, rather than found by walking the children of `parent`. The DOM is
+/// only touched to insert, move and remove nodes. Afterwards `nodes` holds the nodes of
+/// `new_items` in order. If an error is returned `nodes` is left as it was, though the
+/// DOM may have been partly changed, so clear the list before rendering it again.
+///
+/// Nodes are inserted at the end of the list before `after_node`.
+#[allow(clippy::too_many_arguments)]
+pub fn reconcile_nodes<N, Item, Key, GetKey, CreateNode, UpdateNode>(
+    parent: &N,
+    rendered_ids: &[Key],
+    nodes: &mut Vec<N>,
+    new_items: &mut [Item],
+    get_key: GetKey,
+    create_node: CreateNode,
+    update_node: UpdateNode,
+    after_node: Option<N>,
+) -> Result<ReconcileStats, Stage0Error>
+where
+    N: Dom,
+    GetKey: Fn(&Item) -> Key,
+    CreateNode: FnMut(&mut Item) -> N,
+    UpdateNode: FnMut(&N, &mut Item),
+    Key: Eq + Hash,
+{
+    reconcile_nodes_with(
+        parent,
+        rendered_ids,
+        nodes,
+        new_items,
+        get_key,
+        create_node,
+        update_node,
+        after_node,
+        |parent: &N, node: &N| parent.remove_child(node),
+    )
+}
+
+/// Like `reconcile_nodes`, but hands each removed node to `on_remove` along with a `done`
+/// callback that detaches it, e.g. to play an exit animation first. Leaving nodes stay
+/// where they are until `done` is called and aren't in `nodes` any more, so later calls
+/// place the remaining items around them.
+#[allow(clippy::too_many_arguments)]
+pub fn reconcile_nodes_deferred<N, Item, Key, GetKey, CreateNode, UpdateNode, OnRemove>(
+    parent: &N,
+    rendered_ids: &[Key],
+    nodes: &mut Vec<N>,
+    new_items: &mut [Item],
+    get_key: GetKey,
+    create_node: CreateNode,
+    update_node: UpdateNode,
+    after_node: Option<N>,
+    mut on_remove: OnRemove,
+) -> Result<ReconcileStats, Stage0Error>
+where
+    N: Dom + 'static,
+    GetKey: Fn(&Item) -> Key,
+    CreateNode: FnMut(&mut Item) -> N,
+    UpdateNode: FnMut(&N, &mut Item),
+    OnRemove: FnMut(&N, Box<dyn FnOnce()>),
+    Key: Eq + Hash,
+{
+    reconcile_nodes_with(
+        parent,
+        rendered_ids,
+        nodes,
+        new_items,
+        get_key,
+        create_node,
+        update_node,
+        after_node,
+        |parent: &N, node: &N| {
+            remove_later(parent, node, &mut on_remove);
+            Ok(())
+        },
+    )
+}
+
+// `reconcile_nodes`, removing nodes with `on_remove`. Since the rendered nodes aren't
+// found by walking the DOM, `on_remove` can leave them attached for a while.
+#[allow(clippy::too_many_arguments)]
+fn reconcile_nodes_with<N, Item, Key, GetKey, CreateNode, UpdateNode, Remove>(
+    parent: &N,
+    rendered_ids: &[Key],
+    nodes: &mut Vec<N>,
+    new_items: &mut [Item],
+    get_key: GetKey,
+    mut create_node: CreateNode,
+    mut update_node: UpdateNode,
+    after_node: Option<N>,
+    on_remove: Remove,
+) -> Result<ReconcileStats, Stage0Error>
+where
+    N: Dom,
+    GetKey: Fn(&Item) -> Key,
+    CreateNode: FnMut(&mut Item) -> N,
+    UpdateNode: FnMut(&N, &mut Item),
+    Remove: FnMut(&N, &N) -> Result<(), Stage0Error>,
+    Key: Eq + Hash,
+{
+    if nodes.len() != rendered_ids.len() {
+        return Err(Stage0Error::RenderedCount {
+            expected: rendered_ids.len(),
+            found: nodes.len(),
+        });
+    }
+    let (new_ids, prepared) = prepare(
+        rendered_ids,
+        nodes,
+        new_items,
+        get_key,
+        &mut |item: &mut Item| Ok::<_, Stage0Error>(create_node(item)),
+        &mut |node: &N, item: &mut Item| {
+            update_node(node, item);
+            Ok(())
+        },
+    )?;
+    let stats = place(
+        parent,
+        rendered_ids,
+        nodes,
+        &new_ids,
+        &prepared,
+        after_node,
+        on_remove,
+    )?;
+    *nodes = prepared;
+    Ok(stats)
+}
+
+/// Like `reconcile_nodes`, for items that render to several sibling nodes, like a
+/// `<dt>` and `<dd>` pair. `create_node` returns a node or a fragment, whose children are
+/// then kept together in `ranges` and inserted, moved and removed as one.
+///
+/// As the ranges mark where the list is, `after_node` can be left out even if static
+/// siblings follow it: new items then go after the last range, or straight after
+/// `before_node`, or first in `parent` without one, while the list is empty. The callbacks can fail, with the same
+/// guarantees as `try_reconcile`. `ranges` is left as it was on any error, though one
+/// from the DOM may leave it partly changed.
+#[allow(clippy::too_many_arguments)]
+pub fn reconcile_ranges<N, Item, Key, GetKey, CreateNode, UpdateNode, E>(
+    parent: &N,
+    rendered_ids: &[Key],
+    ranges: &mut Vec<NodeRange<N>>,
+    new_items: &mut [Item],
+    get_key: GetKey,
+    mut create_node: CreateNode,
+    mut update_node: UpdateNode,
+    before_node: Option<N>,
+    after_node: Option<N>,
+) -> Result<ReconcileStats, E>
+where
+    N: Dom,
+    GetKey: Fn(&Item) -> Key,
+    CreateNode: FnMut(&mut Item) -> Result<N, E>,
+    UpdateNode: FnMut(&NodeRange<N>, &mut Item) -> Result<(), E>,
+    Key: Eq + Hash,
+    E: From<Stage0Error>,
+{
+    if ranges.len() != rendered_ids.len() {
+        return Err(Stage0Error::RenderedCount {
+            expected: rendered_ids.len(),
+            found: ranges.len(),
+        }
+        .into());
+    }
+    let after_node = after_node.or_else(|| match ranges.last() {
+        Some(range) => range.last().next_sibling(),
+        None => match before_node {
+            Some(node) => node.next_sibling(),
+            None => parent.first_child(),
+        },
+    });
+    let (new_ids, prepared) = prepare(
+        rendered_ids,
+        ranges,
+        new_items,
+        get_key,
+        &mut |item: &mut Item| Ok(NodeRange::new(create_node(item)?)?),
+        &mut update_node,
+    )?;
+
+    let stats = place(
+        parent,
+        rendered_ids,
+        ranges,
+        &new_ids,
+        &prepared,
+        after_node,
+        |parent: &N, range: &NodeRange<N>| range.remove_from(parent),
+    )?;
+    *ranges = prepared;
+    Ok(stats)
+}
+
+/// A smaller `reconcile` that only reuses nodes through its common prefix, suffix and
+/// swap fast paths. Whatever is left in the middle is removed and created again, which
+/// is cheaper for lists that are mostly appended to, trimmed or have two items swapped,
+/// and keeps the move-minimising code out of the binary.
+#[allow(clippy::too_many_arguments)]
+pub fn reconcile_reduced<N, Item, Key, GetKey, CreateNode, UpdateNode>(
+    parent: &N,
+    rendered_ids: &[Key],
+    new_items: &mut [Item],
+    get_key: GetKey,
+    mut create_node: CreateNode,
+    mut update_node: UpdateNode,
+    before_node: Option<N>,
+    after_node: Option<N>,
+) -> Result<ReconcileStats, Stage0Error>
+where
+    N: Dom,
+    GetKey: Fn(&Item) -> Key,
+    CreateNode: FnMut(&mut Item) -> N,
+    UpdateNode: FnMut(&N, &mut Item),
+    Key: Eq + Hash,
+{
+    let mut recorder = Recorder::new(
+        |_: Operation<'_, N>| {},
+        |parent: &N, node: &N| parent.remove_child(node),
+    );
+    let middle = reconcile_ends(
+        parent,
+        rendered_ids,
+        Rendered::After(before_node),
+        new_items,
+        get_key,
+        &mut create_node,
+        &mut update_node,
+        after_node,
+        &mut recorder,
+    )?;
+    let Middle {
+        nodes,
+        prev,
+        new,
+        tail,
+        ..
+    } = match middle {
+        Some(middle) => middle,
+        None => return Ok(recorder.stats),
+    };
 
-    // Index to resolve position from current to new
-    let mut indices: HashMap<Id, usize> = HashMap::new();
-    for i in new_start..=new_end {
-        indices.insert(get_item_id(&new_items[i]), i);
+    // Fallback to replace the middle
+    recorder.stats.path = ReconcilePath::Replace;
+    for node in nodes[prev].iter() {
+        recorder.remove(parent, node)?;
     }
+    for item in new_items[new].iter_mut() {
+        let node = create_node(item);
+        node.insert_into(parent, tail.as_ref())?;
+        recorder.record(Operation::Create(&node));
+    }
+    Ok(recorder.stats)
+}
 
-    let mut reusing_nodes = new_start + new_items.len() - 1 - new_end;
-    let mut to_remove: Vec<usize> = Vec::new();
+/// Like `reconcile`, but for items without a stable identity. The first `rendered_len`
+/// nodes after `before_node` are updated in place with the items at the same position,
+/// then nodes are created for any extra items or removed past the end of `new_items`.
+/// Nothing is ever moved, so the returned stats only count created, updated and removed
+/// nodes.
+#[allow(clippy::too_many_arguments)]
+pub fn reconcile_non_keyed<N, Item, CreateNode, UpdateNode>(
+    parent: &N,
+    rendered_len: usize,
+    new_items: &mut [Item],
+    mut create_node: CreateNode,
+    mut update_node: UpdateNode,
+    before_node: Option<N>,
+    after_node: Option<N>,
+) -> Result<ReconcileStats, Stage0Error>
+where
+    N: Dom,
+    CreateNode: FnMut(&mut Item) -> N,
+    UpdateNode: FnMut(&N, &mut Item),
+{
+    let nodes = rendered_nodes(parent, rendered_len, before_node.as_ref())?;
+    let path = if new_items.is_empty() {
+        ReconcilePath::Clear
+    } else if nodes.is_empty() {
+        ReconcilePath::Create
+    } else if new_items.len() > nodes.len() {
+        ReconcilePath::Add
+    } else if new_items.len() < nodes.len() {
+        ReconcilePath::Shrink
+    } else {
+        ReconcilePath::Ends
+    };
+    let mut stats = ReconcileStats {
+        created: 0,
+        updated: 0,
+        moved: 0,
+        removed: 0,
+        path,
+    };
+    for (node, item) in nodes.iter().zip(new_items.iter_mut()) {
+        update_node(node, item);
+        stats.updated += 1;
+    }
 
-    for i in prev_start..=prev_end {
-        if indices.contains_key(&rendered_ids[i]) {
-            positions[indices[&rendered_ids[i]]] = i as isize;
-            reusing_nodes += 1;
+    // Fast path for add
+    for item in new_items.iter_mut().skip(nodes.len()) {
+        let node = create_node(item);
+        parent.insert_before(&node, after_node.as_ref())?;
+        stats.created += 1;
+    }
+
+    // Fast path for shrink
+    for node in nodes.iter().skip(new_items.len()) {
+        parent.remove_child(node)?;
+        stats.removed += 1;
+    }
+    Ok(stats)
+}
+
+// Returns the indices of `positions` that form the longest strictly increasing
+// subsequence of its `Some` values, in order. Patience sorting, as in
+// https://github.com/adamhaile/surplus/blob/master/src/runtime/content.ts#L368
+fn longest_increasing_subsequence(positions: &[Option<usize>]) -> Vec<usize> {
+    // `tails[k]` is the index ending the best subsequence of length `k + 1` so far.
+    let mut tails: Vec<usize> = Vec::new();
+    let mut prev: Vec<Option<usize>> = vec![None; positions.len()];
+
+    for (i, n) in positions.iter().enumerate() {
+        let n = match n {
+            Some(n) => *n,
+            None => continue,
+        };
+        let k = tails.partition_point(|&t| positions[t].unwrap_or_default() < n);
+        if k > 0 {
+            prev[i] = Some(tails[k - 1]);
+        }
+        if k == tails.len() {
+            tails.push(i);
         } else {
-            to_remove.push(i);
+            tails[k] = i;
         }
     }
 
-    // Fast path for full replace
-    if reusing_nodes == 0 {
-        if before_node.is_some() || after_node.is_some() {
-            let mut node = if let Some(before_node) = &before_node {
-                before_node.next_sibling()
-            } else {
-                parent.first_child()
-            };
+    let mut seq = Vec::with_capacity(tails.len());
+    let mut i = tails.last().copied();
+    while let Some(idx) = i {
+        seq.push(idx);
+        i = prev[idx];
+    }
+    seq.reverse();
+    seq
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        longest_increasing_subsequence, reconcile, reconcile_nodes, reconcile_nodes_deferred,
+        reconcile_non_keyed, reconcile_observed, reconcile_ranges, reconcile_reduced,
+        try_reconcile, Operation, ReconcilePath, ReconcileStats,
+    };
+    use crate::dom::{Dom, MemoryNode, NodeKind};
+    use crate::Stage0Error;
+    use std::cell::Cell;
+
+    fn children(parent: &MemoryNode) -> Vec<MemoryNode> {
+        let mut nodes = Vec::new();
+        let mut node = parent.first_child();
+        while let Some(current) = node {
+            node = current.next_sibling();
+            nodes.push(current);
+        }
+        nodes
+    }
 
-            while let Some(n) = node {
-                if n.is_same_node(after_node.as_ref()) {
-                    break;
+    fn values(parent: &MemoryNode) -> Vec<String> {
+        children(parent)
+            .iter()
+            .map(|node| node.node_value().unwrap_or_default())
+            .collect()
+    }
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum Mode {
+        Full,
+        Reduced,
+        Nodes,
+        Try,
+    }
+
+    fn check(prev: &[u32], next: &[u32], before: bool, after: bool) -> ReconcileStats {
+        check_with(prev, next, before, after, Mode::Full)
+    }
+
+    // Renders `prev`, reconciles it to `next` and checks the result, that every kept
+    // key reused its node, and that the static nodes around the window are untouched.
+    // `Mode::Reduced` only requires reused nodes to belong to a kept key.
+    fn check_with(
+        prev: &[u32],
+        next: &[u32],
+        before: bool,
+        after: bool,
+        mode: Mode,
+    ) -> ReconcileStats {
+        let reduced = mode == Mode::Reduced;
+        let parent = MemoryNode::element("ul");
+        let before_node = if before {
+            let node = MemoryNode::comment("before");
+            parent.append_child(&node).unwrap();
+            Some(node)
+        } else {
+            None
+        };
+        for key in prev {
+            parent
+                .append_child(&MemoryNode::text(&key.to_string()))
+                .unwrap();
+        }
+        let after_node = if after {
+            let node = MemoryNode::comment("after");
+            parent.append_child(&node).unwrap();
+            Some(node)
+        } else {
+            None
+        };
+        let old = children(&parent);
+        let mut nodes: Vec<MemoryNode> = old[before as usize..old.len() - after as usize].to_vec();
+
+        let created = Cell::new(0);
+        let updated = Cell::new(0);
+        let create = |key: &mut u32| {
+            created.set(created.get() + 1);
+            MemoryNode::text(&key.to_string())
+        };
+        let update = |node: &MemoryNode, key: &mut u32| {
+            updated.set(updated.get() + 1);
+            assert_eq!(node.node_value(), Some(key.to_string()));
+        };
+        let mut items = next.to_vec();
+        let (before_node, after_node) = (before_node.clone(), after_node.clone());
+        let stats = match mode {
+            Mode::Reduced => reconcile_reduced(
+                &parent,
+                prev,
+                &mut items,
+                |key| *key,
+                &create,
+                &update,
+                before_node,
+                after_node,
+            ),
+            Mode::Full => reconcile(
+                &parent,
+                prev,
+                &mut items,
+                |key| *key,
+                &create,
+                &update,
+                before_node,
+                after_node,
+            ),
+            Mode::Nodes => reconcile_nodes(
+                &parent,
+                prev,
+                &mut nodes,
+                &mut items,
+                |key| *key,
+                &create,
+                &update,
+                after_node,
+            ),
+            Mode::Try => try_reconcile(
+                &parent,
+                prev,
+                &mut items,
+                |key| *key,
+                |key| Ok::<_, Stage0Error>(create(key)),
+                |node, key| {
+                    update(node, key);
+                    Ok(())
+                },
+                before_node,
+                after_node,
+            ),
+        }
+        .unwrap();
+        if mode == Mode::Nodes {
+            let new = children(&parent);
+            assert_eq!(nodes, &new[before as usize..new.len() - after as usize]);
+        }
+
+        let mut expected: Vec<String> = next.iter().map(ToString::to_string).collect();
+        if before {
+            expected.insert(0, "before".to_owned());
+        }
+        if after {
+            expected.push("after".to_owned());
+        }
+        assert_eq!(values(&parent), expected, "{:?} -> {:?}", prev, next);
+
+        for node in children(&parent) {
+            if node.kind() == NodeKind::Text {
+                let key: u32 = node.node_value().unwrap().parse().unwrap();
+                if reduced {
+                    assert!(!old.contains(&node) || prev.contains(&key));
+                } else {
+                    assert_eq!(old.contains(&node), prev.contains(&key));
                 }
-                let tmp = n.next_sibling();
-                parent.remove_child(&n).unwrap();
-                node = tmp;
-                prev_start += 1;
+            } else {
+                assert!(old.contains(&node));
             }
+        }
+        if reduced {
+            assert_eq!(created.get() + updated.get(), next.len());
         } else {
-            parent.set_text_content(None);
+            let kept = next.iter().filter(|key| prev.contains(key)).count();
+            assert_eq!(created.get(), next.len() - kept);
+            assert_eq!(updated.get(), kept);
         }
+        assert_eq!(
+            (stats.created, stats.updated),
+            (created.get(), updated.get())
+        );
+        assert_eq!(stats.removed, prev.len() - updated.get());
+        assert!(stats.moved <= stats.updated);
+        stats
+    }
 
-        for i in new_start..=new_end {
-            let node = create_node(&mut new_items[i]);
-            if let Some(after_node) = &after_node {
-                parent.insert_before(&node, Some(after_node)).unwrap();
-            } else {
-                parent.append_child(&node).unwrap();
+    // Every sequence of distinct keys drawn from `0..keys`, up to `len` long.
+    fn sequences(keys: u32, len: usize) -> Vec<Vec<u32>> {
+        let mut all = vec![Vec::new()];
+        let mut last = vec![Vec::new()];
+        for _ in 0..len {
+            let mut next = Vec::new();
+            for seq in last.iter() {
+                for key in 0..keys {
+                    if !seq.contains(&key) {
+                        let mut seq = seq.clone();
+                        seq.push(key);
+                        next.push(seq);
+                    }
+                }
             }
+            all.extend(next.iter().cloned());
+            last = next;
         }
+        all
+    }
 
-        return;
+    #[test]
+    fn permutation_tests() {
+        let seqs = sequences(5, 4);
+        for prev in seqs.iter() {
+            for next in seqs.iter() {
+                check(prev, next, false, false);
+            }
+        }
     }
 
-    // What else?
-    let longest_seq = longest_positive_increasing_subsequence(&positions, new_start);
+    #[test]
+    fn window_permutation_tests() {
+        let seqs = sequences(4, 4);
+        for &(before, after) in [(true, false), (false, true), (true, true)].iter() {
+            for prev in seqs.iter() {
+                for next in seqs.iter() {
+                    check(prev, next, before, after);
+                }
+            }
+        }
+    }
 
-    // Collect nodes to work with them
-    let mut nodes: Vec<Node> = Vec::new();
-    let mut tmp_c = prev_start_node.clone();
-    for i in prev_start..=prev_end {
-        if let Some(n) = tmp_c {
-            tmp_c = n.next_sibling();
-            nodes[i] = n;
+    #[test]
+    fn duplicate_key_permutation_tests() {
+        let seqs = sequences(3, 3);
+        for prev in seqs.iter() {
+            for next in seqs.iter().filter(|seq| !seq.is_empty()) {
+                for pos in 0..=next.len() {
+                    let mut items = next.clone();
+                    items.insert(pos, next[0]);
+                    for &mode in [Mode::Full, Mode::Reduced, Mode::Nodes, Mode::Try].iter() {
+                        let parent = MemoryNode::element("ul");
+                        for key in prev {
+                            parent
+                                .append_child(&MemoryNode::text(&key.to_string()))
+                                .unwrap();
+                        }
+                        let mut nodes = children(&parent);
+                        let old = nodes.clone();
+                        let create = |key: &mut u32| MemoryNode::text(&key.to_string());
+                        let update = |_: &MemoryNode, _: &mut u32| {};
+                        let result = match mode {
+                            Mode::Full => reconcile(
+                                &parent,
+                                prev,
+                                &mut items,
+                                |key| *key,
+                                create,
+                                update,
+                                None,
+                                None,
+                            ),
+                            Mode::Reduced => reconcile_reduced(
+                                &parent,
+                                prev,
+                                &mut items,
+                                |key| *key,
+                                create,
+                                update,
+                                None,
+                                None,
+                            ),
+                            Mode::Nodes => reconcile_nodes(
+                                &parent,
+                                prev,
+                                &mut nodes,
+                                &mut items,
+                                |key| *key,
+                                create,
+                                update,
+                                None,
+                            ),
+                            Mode::Try => try_reconcile(
+                                &parent,
+                                prev,
+                                &mut items,
+                                |key| *key,
+                                |key| Ok(create(key)),
+                                |_, _| Ok(()),
+                                None,
+                                None,
+                            ),
+                        };
+                        assert_eq!(
+                            result.unwrap_err(),
+                            Stage0Error::DuplicateKey(pos.max(1)),
+                            "{:?} -> {:?}",
+                            prev,
+                            items
+                        );
+                        assert_eq!(children(&parent), old);
+                        assert_eq!(nodes, old);
+                    }
+                }
+            }
         }
     }
 
-    for i in to_remove {
-        parent.remove_child(&nodes[i]).unwrap();
+    #[test]
+    fn fast_path_tests() {
+        // Prefix and suffix skipping
+        let stats = check(&[1, 2, 3, 4, 5], &[1, 2, 6, 4, 5], false, false);
+        assert_eq!((stats.created, stats.updated), (1, 4));
+        assert_eq!(stats.path, ReconcilePath::Replace);
+        let stats = check(&[1, 2, 3], &[1, 2, 3, 4], true, true);
+        assert_eq!((stats.created, stats.updated), (1, 3));
+        assert_eq!(stats.path, ReconcilePath::Add);
+        let stats = check(&[0, 1, 2, 3], &[1, 2, 3], true, false);
+        assert_eq!((stats.created, stats.updated, stats.removed), (0, 3, 1));
+        assert_eq!(stats.path, ReconcilePath::Shrink);
+        let stats = check(&[1, 2, 3], &[1, 2, 3], false, false);
+        assert_eq!((stats.updated, stats.moved), (3, 0));
+        assert_eq!(stats.path, ReconcilePath::Ends);
+
+        // Swaps
+        let stats = check(&[1, 2, 3, 4, 5], &[1, 4, 3, 2, 5], false, false);
+        assert_eq!((stats.created, stats.updated, stats.moved), (0, 5, 3));
+        assert_eq!(stats.path, ReconcilePath::Ends);
+        check(&[1, 2, 3, 4, 5], &[5, 2, 3, 4, 1], false, true);
+        check(&[1, 2], &[2, 1], true, true);
+
+        // Moves around the longest increasing subsequence
+        let stats = check(&[1, 2, 3, 4, 5], &[1, 3, 5, 2, 4], false, false);
+        assert_eq!((stats.created, stats.updated, stats.moved), (0, 5, 2));
+        assert_eq!(stats.path, ReconcilePath::Lis);
+
+        // Full replace
+        let stats = check(&[1, 2, 3], &[4, 5, 6], true, true);
+        assert_eq!((stats.created, stats.updated, stats.removed), (3, 0, 3));
+        assert_eq!(stats.path, ReconcilePath::Replace);
+        let stats = check(&[1, 2, 3], &[], true, true);
+        assert_eq!((stats.created, stats.removed), (0, 3));
+        assert_eq!(stats.path, ReconcilePath::Clear);
+        let stats = check(&[], &[1, 2], false, false);
+        assert_eq!((stats.created, stats.updated), (2, 0));
+        assert_eq!(stats.path, ReconcilePath::Create);
+    }
+
+    #[test]
+    fn observer_tests() {
+        let parent = MemoryNode::element("ul");
+        for key in 1..=3 {
+            parent
+                .append_child(&MemoryNode::text(&key.to_string()))
+                .unwrap();
+        }
+        let mut ops = Vec::new();
+        let mut items = vec![3, 1, 4];
+        reconcile_observed(
+            &parent,
+            &[1, 2, 3],
+            &mut items,
+            |key| *key,
+            |key| MemoryNode::text(&key.to_string()),
+            |_, _| {},
+            None,
+            None,
+            |op| {
+                ops.push(match op {
+                    Operation::Create(node) => format!("create {}", node.to_html()),
+                    Operation::Update(node) => format!("update {}", node.to_html()),
+                    Operation::Move(node) => format!("move {}", node.to_html()),
+                    Operation::Remove(node) => format!("remove {}", node.to_html()),
+                })
+            },
+        )
+        .unwrap();
+        assert_eq!(values(&parent), vec!["3", "1", "4"]);
+        assert_eq!(
+            ops,
+            vec!["update 3", "move 3", "update 1", "remove 2", "create 4"]
+        );
     }
 
-    let mut lis_idx = longest_seq.len() - 1;
-    for i in new_end..=new_start {
-        if longest_seq[lis_idx] == i as isize {
-            after_node =
-                Some(nodes[positions[longest_seq[lis_idx as usize] as usize] as usize].clone());
-            if let Some(after_node) = &after_node {
-                update_node(after_node, &mut new_items[i]);
+    #[test]
+    fn nodes_permutation_tests() {
+        let seqs = sequences(4, 4);
+        for &(before, after) in [(false, false), (true, true)].iter() {
+            for prev in seqs.iter() {
+                for next in seqs.iter() {
+                    check_with(prev, next, before, after, Mode::Nodes);
+                }
             }
-            lis_idx -= 1;
-        } else {
-            let tmp_d = if positions[i] == -1 {
-                create_node(&mut new_items[i])
-            } else {
-                let n = &nodes[positions[i] as usize];
-                update_node(n, &mut new_items[i]);
-                n.clone()
-            };
-            parent.insert_before(&tmp_d, after_node.as_ref()).unwrap();
-            after_node = Some(tmp_d);
         }
     }
-}
 
-// Picked from
-// https://github.com/adamhaile/surplus/blob/master/src/runtime/content.ts#L368
-// return an array of the indices of ns that comprise the longest increasing subsequence within ns
-fn longest_positive_increasing_subsequence(positions: &[isize], new_start: usize) -> Vec<isize> {
-    let mut seq = Vec::new();
-    let mut is = Vec::new();
-    let mut l = -1_isize;
-    let mut pre = Vec::with_capacity(positions.len());
+    #[test]
+    fn nodes_mismatch_tests() {
+        let parent = MemoryNode::element("ul");
+        let mut nodes = vec![MemoryNode::text("1")];
+        parent.append_child(&nodes[0]).unwrap();
+        let old = nodes.clone();
+        let mut items = vec![2, 1];
+        let created = Cell::new(0);
+        let result = reconcile_nodes(
+            &parent,
+            &[1, 2],
+            &mut nodes,
+            &mut items,
+            |key| *key,
+            |key| {
+                created.set(created.get() + 1);
+                MemoryNode::text(&key.to_string())
+            },
+            |_, _| {},
+            None,
+        );
+        assert!(result.is_err());
+        assert_eq!(created.get(), 0);
+        assert_eq!(nodes, old);
 
-    for i in new_start..positions.len() {
-        let n = positions[i];
-        if n < 0 {
-            continue;
+        // A node that isn't in `parent` can't be removed.
+        nodes.push(MemoryNode::text("2"));
+        let old = nodes.clone();
+        let mut items = vec![1];
+        let result = reconcile_nodes(
+            &parent,
+            &[1, 2],
+            &mut nodes,
+            &mut items,
+            |key| *key,
+            |key| MemoryNode::text(&key.to_string()),
+            |_, _| {},
+            None,
+        );
+        assert!(result.is_err());
+        assert_eq!(nodes, old);
+    }
+
+    #[test]
+    fn nodes_deferred_tests() {
+        let parent = MemoryNode::element("ul");
+        let mut nodes = Vec::new();
+        let mut leaving = Vec::new();
+        let mut rendered: Vec<u32> = Vec::new();
+        // The leaving `2` stays put while the others move around it.
+        let steps = [
+            (vec![1, 2, 3], vec!["1", "2", "3"]),
+            (vec![3, 1], vec!["3", "2", "1"]),
+            (vec![1, 4, 3], vec!["1", "2", "4", "3"]),
+        ];
+        for (items, expected) in steps.iter() {
+            let mut items = items.clone();
+            reconcile_nodes_deferred(
+                &parent,
+                &rendered,
+                &mut nodes,
+                &mut items,
+                |key| *key,
+                |key| MemoryNode::text(&key.to_string()),
+                |_, _| {},
+                None,
+                |node, done| leaving.push((node.clone(), done)),
+            )
+            .unwrap();
+            assert_eq!(values(&parent), *expected);
+            rendered = items;
         }
 
-        let j = find_greatest_index_leq(&seq, n);
-        if j != -1 {
-            pre[i] = is[j as usize];
+        let (node, done) = leaving.remove(0);
+        assert_eq!(node.node_value().as_deref(), Some("2"));
+        done();
+        assert_eq!(values(&parent), vec!["1", "4", "3"]);
+        assert_eq!(nodes, children(&parent));
+    }
+
+    #[test]
+    fn try_permutation_tests() {
+        let seqs = sequences(4, 4);
+        for &(before, after) in [(false, false), (true, true)].iter() {
+            for prev in seqs.iter() {
+                for next in seqs.iter() {
+                    check_with(prev, next, before, after, Mode::Try);
+                }
+            }
         }
-        if j == 1 {
-            l += 1;
-            seq[l as usize] = n;
-            is[l as usize] = i;
-        } else if n < seq[(j + 1) as usize] {
-            seq[(j + 1) as usize] = n;
-            is[(j + 1) as usize] = i;
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum TryError {
+        Failed(&'static str),
+        Reconcile,
+    }
+
+    impl From<Stage0Error> for TryError {
+        fn from(_: Stage0Error) -> TryError {
+            TryError::Reconcile
         }
     }
 
-    {
-        let mut i = is[l as usize];
-        while l >= 0 {
-            seq[l as usize] = i as isize;
-            i = pre[i];
-            l -= 1;
+    #[test]
+    fn try_error_tests() {
+        let parent = MemoryNode::parse("<ul><!--a--><!--b--><!--c--></ul>").unwrap();
+        let old = children(&parent);
+        let create_fails = |key: &mut &str| match *key {
+            "x" => Err(TryError::Failed("x")),
+            key => Ok(MemoryNode::comment(key)),
+        };
+        let update_fails = |_: &MemoryNode, key: &mut &str| match *key {
+            "b" => Err(TryError::Failed("b")),
+            _ => Ok(()),
+        };
+
+        let result = try_reconcile(
+            &parent,
+            &["a", "b", "c"],
+            &mut ["c", "d", "x", "a"],
+            |key| *key,
+            create_fails,
+            |_, _| Ok(()),
+            None,
+            None,
+        );
+        assert_eq!(result.unwrap_err(), TryError::Failed("x"));
+        assert_eq!(children(&parent), old);
+
+        let result = try_reconcile(
+            &parent,
+            &["a", "b", "c"],
+            &mut ["c", "b", "a"],
+            |key| *key,
+            create_fails,
+            update_fails,
+            None,
+            None,
+        );
+        assert_eq!(result.unwrap_err(), TryError::Failed("b"));
+        assert_eq!(children(&parent), old);
+
+        let result: Result<_, Stage0Error> = try_reconcile(
+            &parent,
+            &["a", "b", "c"],
+            &mut ["a", "d", "a"],
+            |key| *key,
+            |key| Ok(MemoryNode::comment(key)),
+            |_, _| Ok(()),
+            None,
+            None,
+        );
+        assert_eq!(result.unwrap_err(), Stage0Error::DuplicateKey(2));
+        assert_eq!(children(&parent), old);
+    }
+
+    // Renders each key as a `<dt>` and `<dd>` pair.
+    fn definition(key: &mut u32) -> Result<MemoryNode, Stage0Error> {
+        let fragment = MemoryNode::fragment();
+        for name in ["dt", "dd"].iter() {
+            let el = MemoryNode::element(name);
+            el.append_child(&MemoryNode::text(&key.to_string()))?;
+            fragment.append_child(&el)?;
         }
+        Ok(fragment)
     }
 
-    seq
-}
+    fn definitions(keys: &[u32]) -> String {
+        keys.iter()
+            .map(|key| format!("<dt>{0}</dt><dd>{0}</dd>", key))
+            .collect()
+    }
+
+    #[test]
+    fn range_permutation_tests() {
+        let seqs = sequences(4, 4);
+        // Without `after_node`, a static sibling can follow the list unannounced.
+        let cases = [
+            (false, false, false),
+            (true, true, true),
+            (true, false, true),
+            (false, false, true),
+        ];
+        for &(before, after, trailing) in cases.iter() {
+            for prev in seqs.iter() {
+                for next in seqs.iter() {
+                    let parent = MemoryNode::element("dl");
+                    let before_node = MemoryNode::comment("before");
+                    let after_node = MemoryNode::comment("after");
+                    if before {
+                        parent.append_child(&before_node).unwrap();
+                    }
+                    if trailing {
+                        parent.append_child(&after_node).unwrap();
+                    }
+                    let before_node = if before { Some(before_node) } else { None };
+                    let after_node = if after { Some(after_node) } else { None };
+
+                    let mut ranges = Vec::new();
+                    reconcile_ranges(
+                        &parent,
+                        &[],
+                        &mut ranges,
+                        &mut prev.clone(),
+                        |key| *key,
+                        definition,
+                        |_, _| Ok(()),
+                        before_node.clone(),
+                        after_node.clone(),
+                    )
+                    .unwrap();
+                    let old: Vec<(u32, MemoryNode)> = prev
+                        .iter()
+                        .zip(ranges.iter())
+                        .map(|(key, range)| (*key, range.first().clone()))
+                        .collect();
+
+                    let stats = reconcile_ranges(
+                        &parent,
+                        prev,
+                        &mut ranges,
+                        &mut next.clone(),
+                        |key| *key,
+                        definition,
+                        |range, key| {
+                            assert_eq!(range.first().to_html(), format!("<dt>{}</dt>", key));
+                            assert_eq!(range.nodes().len(), 2);
+                            Ok::<_, Stage0Error>(())
+                        },
+                        before_node.clone(),
+                        after_node.clone(),
+                    )
+                    .unwrap();
+
+                    let mut expected = definitions(next);
+                    if before {
+                        expected.insert_str(0, "<!--before-->");
+                    }
+                    if trailing {
+                        expected.push_str("<!--after-->");
+                    }
+                    assert_eq!(
+                        parent.to_html(),
+                        format!("<dl>{}</dl>", expected),
+                        "{:?} -> {:?}",
+                        prev,
+                        next
+                    );
+                    assert_eq!(ranges.len(), next.len());
+                    for (key, range) in next.iter().zip(ranges.iter()) {
+                        assert_eq!(range.last().to_html(), format!("<dd>{}</dd>", key));
+                        if let Some((_, first)) = old.iter().find(|(old_key, _)| old_key == key) {
+                            assert_eq!(range.first(), first);
+                        }
+                    }
+                    let kept = next.iter().filter(|key| prev.contains(key)).count();
+                    assert_eq!((stats.created, stats.updated), (next.len() - kept, kept));
+                }
+            }
+        }
+    }
 
-fn find_greatest_index_leq(seq: &[isize], n: isize) -> isize {
-    // invariant: lo is guaranteed to be index of a value <= n, hi to be >
-    // therefore, they actually start out of range: (-1, last + 1)
-    let mut lo = -1_isize;
-    let mut hi = seq.len() as isize;
+    #[test]
+    fn range_error_tests() {
+        let parent = MemoryNode::element("dl");
+        let mut ranges = Vec::new();
+        reconcile_ranges(
+            &parent,
+            &[],
+            &mut ranges,
+            &mut [1, 2],
+            |key| *key,
+            definition,
+            |_, _| Ok(()),
+            None,
+            None,
+        )
+        .unwrap();
 
-    if hi > 0 && seq[(hi - 1) as usize] <= n {
-        return hi - 1;
+        let result: Result<_, Stage0Error> = reconcile_ranges(
+            &parent,
+            &[1, 2],
+            &mut ranges,
+            &mut [2, 3],
+            |key| *key,
+            |_| Ok(MemoryNode::fragment()),
+            |_, _| Ok(()),
+            None,
+            None,
+        );
+        assert_eq!(result.unwrap_err(), Stage0Error::EmptyFragment);
+        assert_eq!(
+            parent.to_html(),
+            format!("<dl>{}</dl>", definitions(&[1, 2]))
+        );
+        assert_eq!(ranges.len(), 2);
+
+        // A single node is a range of its own.
+        reconcile_ranges(
+            &parent,
+            &[1, 2],
+            &mut ranges,
+            &mut [2, 3],
+            |key| *key,
+            |key| Ok::<_, Stage0Error>(MemoryNode::text(&key.to_string())),
+            |_, _| Ok(()),
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(parent.to_html(), "<dl><dt>2</dt><dd>2</dd>3</dl>");
+
+        let result = reconcile_ranges(
+            &parent,
+            &[2],
+            &mut ranges,
+            &mut [],
+            |key: &u32| *key,
+            definition,
+            |_, _| Ok(()),
+            None,
+            None,
+        );
+        assert_eq!(
+            result.unwrap_err(),
+            Stage0Error::RenderedCount {
+                expected: 1,
+                found: 2
+            }
+        );
     }
 
-    while hi - lo > 1 {
-        let mid = (lo + hi) / 2;
-        if seq[mid as usize] > n {
-            hi = mid;
-        } else {
-            lo = mid;
+    #[test]
+    fn reduced_tests() {
+        let seqs = sequences(4, 4);
+        for &(before, after) in [(false, false), (true, true)].iter() {
+            for prev in seqs.iter() {
+                for next in seqs.iter() {
+                    check_with(prev, next, before, after, Mode::Reduced);
+                }
+            }
         }
+
+        // The fast paths still reuse nodes, while the middle is rebuilt.
+        let outcome = check_with(
+            &[1, 2, 3, 4, 5],
+            &[1, 2, 6, 4, 5],
+            false,
+            false,
+            Mode::Reduced,
+        );
+        assert_eq!((outcome.created, outcome.updated), (1, 4));
+        let outcome = check_with(
+            &[1, 2, 3, 4, 5],
+            &[1, 4, 3, 2, 5],
+            true,
+            true,
+            Mode::Reduced,
+        );
+        assert_eq!((outcome.created, outcome.updated), (0, 5));
+        let outcome = check_with(
+            &[1, 2, 3, 4, 5],
+            &[1, 3, 5, 2, 4],
+            false,
+            false,
+            Mode::Reduced,
+        );
+        assert_eq!((outcome.created, outcome.updated), (4, 1));
     }
 
-    lo
+    #[test]
+    fn mismatched_rendered_ids_tests() {
+        let parent = MemoryNode::element("ul");
+        parent.append_child(&MemoryNode::text("1")).unwrap();
+        let mut items = vec![2, 1];
+        let result = reconcile(
+            &parent,
+            &[1, 2],
+            &mut items,
+            |key| *key,
+            |key| MemoryNode::text(&key.to_string()),
+            |_, _| {},
+            None,
+            None,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn non_keyed_tests() {
+        for prev in 0..5 {
+            for next in 0..5 {
+                for &(before, after) in
+                    [(false, false), (true, false), (false, true), (true, true)].iter()
+                {
+                    let parent = MemoryNode::element("ul");
+                    let before_node = MemoryNode::comment("before");
+                    let after_node = MemoryNode::comment("after");
+                    if before {
+                        parent.append_child(&before_node).unwrap();
+                    }
+                    for i in 0..prev {
+                        parent
+                            .append_child(&MemoryNode::text(&format!("old{}", i)))
+                            .unwrap();
+                    }
+                    if after {
+                        parent.append_child(&after_node).unwrap();
+                    }
+                    let old = children(&parent);
+
+                    let created = Cell::new(0);
+                    let mut items: Vec<usize> = (0..next).collect();
+                    let stats = reconcile_non_keyed(
+                        &parent,
+                        prev,
+                        &mut items,
+                        |i| {
+                            created.set(created.get() + 1);
+                            MemoryNode::text(&i.to_string())
+                        },
+                        |node, i| node.set_node_value(Some(&i.to_string())),
+                        if before {
+                            Some(before_node.clone())
+                        } else {
+                            None
+                        },
+                        if after {
+                            Some(after_node.clone())
+                        } else {
+                            None
+                        },
+                    )
+                    .unwrap();
+
+                    let mut expected: Vec<String> = (0..next).map(|i| i.to_string()).collect();
+                    if before {
+                        expected.insert(0, "before".to_owned());
+                    }
+                    if after {
+                        expected.push("after".to_owned());
+                    }
+                    assert_eq!(values(&parent), expected);
+                    assert_eq!(created.get(), next.saturating_sub(prev));
+                    assert_eq!(stats.created, created.get());
+                    assert_eq!(stats.updated, prev.min(next));
+                    assert_eq!(stats.removed, prev.saturating_sub(next));
+                    assert_eq!(stats.moved, 0);
+                    let path = match (prev, next) {
+                        (_, 0) => ReconcilePath::Clear,
+                        (0, _) => ReconcilePath::Create,
+                        (prev, next) if next > prev => ReconcilePath::Add,
+                        (prev, next) if next < prev => ReconcilePath::Shrink,
+                        _ => ReconcilePath::Ends,
+                    };
+                    assert_eq!(stats.path, path);
+
+                    // Nodes are patched in place, never moved.
+                    let new = children(&parent);
+                    let offset = before as usize;
+                    for i in 0..prev.min(next) {
+                        assert_eq!(new[offset + i], old[offset + i]);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn longest_increasing_subsequence_tests() {
+        assert_eq!(longest_increasing_subsequence(&[]), Vec::<usize>::new());
+        assert_eq!(
+            longest_increasing_subsequence(&[None, None]),
+            Vec::<usize>::new()
+        );
+        assert_eq!(
+            longest_increasing_subsequence(&[Some(3), Some(0), None, Some(1), Some(2)]),
+            vec![1, 3, 4]
+        );
+        assert_eq!(
+            longest_increasing_subsequence(&[Some(0), Some(4), Some(1), Some(2), Some(3)]),
+            vec![0, 2, 3, 4]
+        );
+    }
 }
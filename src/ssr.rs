@@ -0,0 +1,9 @@
+//! Renders templates to HTML strings without a DOM, so a server can send the first paint
+//! before the wasm bundle loads. Works on any target, including native `cargo test`.
+//!
+//! ```
+//! let html = stage0::ssr::render("<p>#count items left</p>", vec![("count", "3")]).unwrap();
+//! assert_eq!(html, "<p>3<!----> items left</p>");
+//! ```
+
+pub use stage0_parser::{render, HtmlTemplate};
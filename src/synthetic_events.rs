@@ -1,11 +1,11 @@
-use super::DOCUMENT;
+use super::{Stage0Error, DOCUMENT};
 use js_sys::Reflect;
 use lazy_static::lazy_static;
 use std::collections::HashSet;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 
-fn native_to_synthetic_event(event: web_sys::Event, name: &str) {
+fn native_to_synthetic_event(event: web_sys::Event, name: &str) -> Result<(), JsValue> {
     let mut event_key = String::new();
     event_key.push_str("__");
     event_key.push_str(name);
@@ -15,29 +15,33 @@ fn native_to_synthetic_event(event: web_sys::Event, name: &str) {
         .target()
         .and_then(|et| et.dyn_into::<web_sys::Node>().ok());
     while let Some(node) = dom.take() {
-        let event_handler = Reflect::get(&node, &event_key).unwrap();
+        let event_handler = Reflect::get(&node, &event_key)?;
         if let Some(event_handler) = event_handler.dyn_ref::<js_sys::Function>() {
-            event_handler.call1(&JsValue::NULL, &event).unwrap();
-            return;
+            event_handler.call1(&JsValue::NULL, &event)?;
+            return Ok(());
         }
         dom = node.parent_node();
     }
+    Ok(())
 }
 
 lazy_static! {
     static ref CONFIGURED_SYNTHETIC_EVENTS: HashSet<&'static str> = HashSet::new();
 }
 
-pub fn setup_synthetic_event(name: &'static str) {
+pub fn setup_synthetic_event(name: &'static str) -> Result<(), Stage0Error> {
     if CONFIGURED_SYNTHETIC_EVENTS.contains(name) {
-        return;
+        return Ok(());
     }
     let callback = Closure::wrap(Box::new(move |event: web_sys::Event| {
-        native_to_synthetic_event(event, name);
+        // Rethrow handler errors so they surface like any other listener exception.
+        if let Err(err) = native_to_synthetic_event(event, name) {
+            wasm_bindgen::throw_val(err);
+        }
     }) as Box<dyn Fn(web_sys::Event)>);
     DOCUMENT
         .0
-        .add_event_listener_with_callback(name, callback.as_ref().unchecked_ref())
-        .unwrap();
+        .add_event_listener_with_callback(name, callback.as_ref().unchecked_ref())?;
     callback.forget();
+    Ok(())
 }